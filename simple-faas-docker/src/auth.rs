@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Context};
+use crate::error::{DockerError, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::TryFrom;
@@ -7,15 +7,17 @@ use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 
-pub fn read_default() -> anyhow::Result<DockerConfig> {
-    let home_dir = var("HOME").with_context(|| "HOME env is not set")?;
+pub fn read_default() -> Result<DockerConfig> {
+    let home_dir = var("HOME").map_err(|e| DockerError::config("HOME env is not set", e))?;
     let path = PathBuf::new().join(home_dir).join(".docker/config.json");
-    let docker_config = File::open(path).with_context(|| "Failed to open docker config")?;
+    let docker_config =
+        File::open(path).map_err(|e| DockerError::config("Failed to open docker config", e))?;
     read_auth(docker_config)
 }
 
-pub fn read_auth<R: Read>(reader: R) -> anyhow::Result<DockerConfig> {
-    let raw_config: RawDockerConfig = serde_json::from_reader(reader)?;
+pub fn read_auth<R: Read>(reader: R) -> Result<DockerConfig> {
+    let raw_config: RawDockerConfig = serde_json::from_reader(reader)
+        .map_err(|e| DockerError::config("Invalid docker config", e))?;
     let config: DockerConfig = raw_config.try_into()?;
     Ok(config)
 }
@@ -26,9 +28,9 @@ pub struct DockerConfig {
 }
 
 impl TryFrom<RawDockerConfig> for DockerConfig {
-    type Error = anyhow::Error;
+    type Error = DockerError;
 
-    fn try_from(raw_docker_config: RawDockerConfig) -> Result<Self, Self::Error> {
+    fn try_from(raw_docker_config: RawDockerConfig) -> Result<Self> {
         let mut auths = HashMap::new();
 
         for (domain, raw_auth) in raw_docker_config.auths.into_iter() {
@@ -47,14 +49,17 @@ pub struct DockerAuth {
 }
 
 impl TryFrom<RawDockerAuth> for DockerAuth {
-    type Error = anyhow::Error;
+    type Error = DockerError;
 
-    fn try_from(raw_docker_auth: RawDockerAuth) -> Result<Self, Self::Error> {
-        let decoded = base64::decode(raw_docker_auth.auth)?;
+    fn try_from(raw_docker_auth: RawDockerAuth) -> Result<Self> {
+        let decoded = base64::decode(raw_docker_auth.auth)
+            .map_err(|e| DockerError::config("Invalid registry auth token", e))?;
         let stringified = String::from_utf8_lossy(&decoded);
-        let (username, password) = stringified
-            .split_once(":")
-            .ok_or_else(|| anyhow!("No : delimiter in username/password registry auth token"))?;
+        let (username, password) = stringified.split_once(":").ok_or_else(|| {
+            DockerError::Config(
+                "No : delimiter in username/password registry auth token".to_string(),
+            )
+        })?;
 
         Ok(DockerAuth {
             username: username.into(),
@@ -0,0 +1,204 @@
+use crate::error::{DockerError, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use std::fmt;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const HEADER_LENGTH: usize = 8;
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Standard stream a multiplexed frame belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdStream {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+/// Chunk of container output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub stream: StdStream,
+    pub data: Bytes,
+}
+
+/// Demultiplexed container output.
+pub type FrameStream = BoxStream<'static, Result<Frame>>;
+
+/// Takes next frame out of buffer once it is fully received.
+///
+/// Docker multiplexes output of containers without tty as frames of
+/// 8 byte header `[stream, 0, 0, 0, size (u32 big endian)]` followed by `size` bytes.
+pub(crate) fn decode_frame(buffer: &mut BytesMut) -> std::result::Result<Option<Frame>, String> {
+    if buffer.len() < HEADER_LENGTH {
+        return Ok(None);
+    }
+
+    let stream = match buffer[0] {
+        0 => StdStream::Stdin,
+        1 => StdStream::Stdout,
+        2 => StdStream::Stderr,
+        other => return Err(format!("Unknown multiplexed stream type {}", other)),
+    };
+    let size = u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]) as usize;
+    if buffer.len() < HEADER_LENGTH + size {
+        return Ok(None);
+    }
+
+    buffer.advance(HEADER_LENGTH);
+    let data = buffer.split_to(size).freeze();
+
+    Ok(Some(Frame { stream, data }))
+}
+
+struct FrameState<S> {
+    operation: &'static str,
+    chunks: S,
+    buffer: BytesMut,
+    finished: bool,
+}
+
+/// Demultiplexes http body of logs response or output of hijacked attach connection.
+pub(crate) fn frame_stream<S, E>(operation: &'static str, chunks: S) -> FrameStream
+where
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send + Unpin + 'static,
+    E: fmt::Display,
+{
+    let state = FrameState {
+        operation,
+        chunks,
+        buffer: BytesMut::new(),
+        finished: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if state.finished {
+                return None;
+            }
+
+            match decode_frame(&mut state.buffer) {
+                Ok(Some(frame)) => return Some((Ok(frame), state)),
+                Ok(None) => {}
+                Err(e) => {
+                    state.finished = true;
+                    return Some((Err(DockerError::protocol(state.operation, e)), state));
+                }
+            }
+
+            match state.chunks.next().await {
+                Some(Ok(chunk)) => state.buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    state.finished = true;
+                    return Some((Err(DockerError::protocol(state.operation, e)), state));
+                }
+                None if state.buffer.is_empty() => return None,
+                None => {
+                    state.finished = true;
+                    let error = DockerError::protocol(state.operation, "Truncated output frame");
+                    return Some((Err(error), state));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Reads raw connection as a stream of chunks for [frame_stream].
+pub(crate) fn read_chunks<R>(reader: R) -> BoxStream<'static, io::Result<Bytes>>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    stream::unfold(reader, |mut reader| async move {
+        let mut buffer = BytesMut::with_capacity(READ_CHUNK_SIZE);
+        match reader.read_buf(&mut buffer).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(buffer.freeze()), reader)),
+            Err(e) => Some((Err(e), reader)),
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_frame, frame_stream, read_chunks, Frame, StdStream};
+    use bytes::BytesMut;
+    use futures_util::StreamExt;
+    use hyper::Body;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_decode_frame() {
+        let mut buffer = BytesMut::from(&[1u8, 0, 0, 0, 0, 0, 0, 2, b'h', b'i', 2, 0][..]);
+
+        assert_eq!(
+            decode_frame(&mut buffer).unwrap(),
+            Some(Frame {
+                stream: StdStream::Stdout,
+                data: "hi".into(),
+            })
+        );
+        assert_eq!(decode_frame(&mut buffer).unwrap(), None);
+        assert_eq!(buffer.len(), 2);
+
+        let mut buffer = BytesMut::from(&[7u8, 0, 0, 0, 0, 0, 0, 0][..]);
+        assert!(decode_frame(&mut buffer).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_frame_stream() {
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> = vec![
+            Ok(vec![1, 0, 0, 0, 0, 0, 0, 3, b'o', b'u']),
+            Ok(vec![b't', 2, 0, 0, 0, 0, 0, 0, 3]),
+            Ok(vec![b'e', b'r', b'r', 1, 0, 0, 0, 0, 0, 0, 0]),
+        ];
+        let body = Body::wrap_stream(futures_util::stream::iter(chunks));
+        let frames: Vec<_> = frame_stream("get container logs", body)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+
+        assert_eq!(
+            frames,
+            vec![
+                Frame {
+                    stream: StdStream::Stdout,
+                    data: "out".into(),
+                },
+                Frame {
+                    stream: StdStream::Stderr,
+                    data: "err".into(),
+                },
+                Frame {
+                    stream: StdStream::Stdout,
+                    data: "".into(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_chunks() {
+        let (mut writer, reader) = tokio::io::duplex(64);
+        writer
+            .write_all(&[2, 0, 0, 0, 0, 0, 0, 4, b'o', b'o', b'p', b's'])
+            .await
+            .unwrap();
+        drop(writer);
+
+        let frames: Vec<_> = frame_stream("attach to container", read_chunks(reader))
+            .map(Result::unwrap)
+            .collect()
+            .await;
+
+        assert_eq!(
+            frames,
+            vec![Frame {
+                stream: StdStream::Stderr,
+                data: "oops".into(),
+            }]
+        );
+    }
+}
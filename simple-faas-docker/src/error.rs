@@ -0,0 +1,193 @@
+use hyper::{Body, Response, StatusCode};
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, DockerError>;
+
+/// Error response of docker daemon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    /// Api call that failed, e.g. `create container`.
+    pub operation: &'static str,
+    /// Container id or image reference the call was made for.
+    pub id: Option<String>,
+    pub status: StatusCode,
+    /// `message` of daemon json error body, or raw body if it is not json.
+    pub message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to {}", self.operation)?;
+        if let Some(id) = &self.id {
+            write!(f, " {}", id)?;
+        }
+        write!(f, ": {} ({})", self.message, self.status)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DockerError {
+    /// Daemon rejected request parameters (400).
+    #[error("{0}")]
+    BadRequest(ApiError),
+    /// Daemon or registry refused credentials (401, 403).
+    #[error("{0}")]
+    Unauthorized(ApiError),
+    /// No such container or image (404).
+    #[error("{0}")]
+    NotFound(ApiError),
+    /// Name conflict or container in a wrong state (409).
+    #[error("{0}")]
+    Conflict(ApiError),
+    /// Any other error status, usually daemon side 500.
+    #[error("{0}")]
+    Server(ApiError),
+    /// Connection to daemon could not be established.
+    #[error("Docker daemon is unavailable, failed to {operation}: {source}")]
+    DaemonUnavailable {
+        operation: &'static str,
+        #[source]
+        source: hyper::Error,
+    },
+    /// Daemon did not respond within [PoolConfig::request_timeout](crate::client::PoolConfig).
+    #[error("Docker daemon did not respond to {operation} in {timeout:?}")]
+    Timeout {
+        operation: &'static str,
+        timeout: Duration,
+    },
+    /// Connection broke mid request or daemon response could not be understood.
+    #[error("Unexpected docker daemon response to {operation}: {message}")]
+    Protocol {
+        operation: &'static str,
+        message: String,
+    },
+    /// Daemon reported failure in the middle of image pull progress stream.
+    #[error("Failed to pull image {reference}: {message}")]
+    ImagePull { reference: String, message: String },
+    #[error("Invalid image reference {reference}: {reason}")]
+    InvalidReference { reference: String, reason: String },
+    /// Docker host, TLS settings or docker config can't be used.
+    #[error("{0}")]
+    Config(String),
+}
+
+impl DockerError {
+    /// Daemon error status of the failed call, if daemon responded at all.
+    pub fn status(&self) -> Option<StatusCode> {
+        self.api_error().map(|e| e.status)
+    }
+
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            DockerError::BadRequest(e)
+            | DockerError::Unauthorized(e)
+            | DockerError::NotFound(e)
+            | DockerError::Conflict(e)
+            | DockerError::Server(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Request never reached the daemon, so it is safe to repeat.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, DockerError::DaemonUnavailable { .. })
+    }
+
+    pub(crate) fn config<E: fmt::Display>(message: &str, error: E) -> Self {
+        DockerError::Config(format!("{}: {}", message, error))
+    }
+
+    pub(crate) fn protocol<E: fmt::Display>(operation: &'static str, error: E) -> Self {
+        DockerError::Protocol {
+            operation,
+            message: error.to_string(),
+        }
+    }
+
+    pub(crate) fn from_hyper(operation: &'static str, error: hyper::Error) -> Self {
+        match error.is_connect() {
+            true => DockerError::DaemonUnavailable {
+                operation,
+                source: error,
+            },
+            false => Self::protocol(operation, error),
+        }
+    }
+
+    /// Builds error from unexpected daemon response.
+    pub(crate) async fn from_response(
+        operation: &'static str,
+        id: Option<&str>,
+        response: Response<Body>,
+    ) -> Self {
+        let status = response.status();
+        let body = match hyper::body::to_bytes(response.into_body()).await {
+            Ok(body) => body,
+            Err(e) => return Self::protocol(operation, e),
+        };
+
+        let message = match serde_json::from_slice::<ErrorBody>(&body) {
+            Ok(error_body) => error_body.message,
+            Err(_) => String::from_utf8_lossy(&body).trim().to_string(),
+        };
+
+        let api_error = ApiError {
+            operation,
+            id: id.map(str::to_owned),
+            status,
+            message,
+        };
+
+        match status {
+            StatusCode::BAD_REQUEST => DockerError::BadRequest(api_error),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                DockerError::Unauthorized(api_error)
+            }
+            StatusCode::NOT_FOUND => DockerError::NotFound(api_error),
+            StatusCode::CONFLICT => DockerError::Conflict(api_error),
+            _ => DockerError::Server(api_error),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DockerError;
+    use hyper::{Body, Response, StatusCode};
+
+    #[tokio::test]
+    async fn test_from_response() {
+        let response = Response::builder()
+            .status(404)
+            .body(Body::from(r#"{"message":"No such container: abc"}"#))
+            .unwrap();
+        let error = DockerError::from_response("start container", Some("abc"), response).await;
+
+        assert!(matches!(error, DockerError::NotFound(_)));
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(
+            error.to_string(),
+            "Failed to start container abc: No such container: abc (404 Not Found)"
+        );
+
+        let response = Response::builder()
+            .status(500)
+            .body(Body::from("plain failure\n"))
+            .unwrap();
+        let error = DockerError::from_response("create container", None, response).await;
+
+        assert!(matches!(error, DockerError::Server(_)));
+        assert_eq!(
+            error.api_error().unwrap().message,
+            "plain failure".to_string()
+        );
+    }
+}
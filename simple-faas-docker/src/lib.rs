@@ -1,3 +1,7 @@
 pub mod auth;
 pub mod client;
+pub mod demux;
+pub mod error;
+mod transport;
+pub mod util;
 pub mod v1_37;
@@ -0,0 +1,131 @@
+use crate::client::Host;
+use hyper::client::connect::{Connected, Connection};
+use hyper::service::Service;
+use hyper::Uri;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpStream, UnixStream};
+use tokio_native_tls::{TlsConnector, TlsStream};
+
+/// Hyper connector dialing docker daemon over whatever transport
+/// [Host] points to. Request uri is ignored, every connection goes to the daemon.
+#[derive(Clone)]
+pub(crate) struct Connector {
+    host: Host,
+    tls: Option<TlsConnector>,
+}
+
+impl Connector {
+    pub(crate) fn new(host: Host, tls: Option<native_tls::TlsConnector>) -> Self {
+        Connector {
+            host,
+            tls: tls.map(TlsConnector::from),
+        }
+    }
+}
+
+impl Service<Uri> for Connector {
+    type Response = Stream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Stream, io::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: Uri) -> Self::Future {
+        let host = self.host.clone();
+        let tls = self.tls.clone();
+
+        Box::pin(async move {
+            match host {
+                Host::Tcp(address) => {
+                    let stream = TcpStream::connect(&address).await?;
+                    stream.set_nodelay(true)?;
+
+                    match tls {
+                        Some(tls) => {
+                            let stream = tls
+                                .connect(domain(&address), stream)
+                                .await
+                                .map_err(io::Error::other)?;
+                            Ok(Stream::Tls(Box::new(stream)))
+                        }
+                        None => Ok(Stream::Tcp(stream)),
+                    }
+                }
+                Host::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path).await?)),
+            }
+        })
+    }
+}
+
+/// Strips port and ipv6 brackets from `host:port` to verify certificate against.
+fn domain(address: &str) -> &str {
+    let host = match address.rsplit_once(':') {
+        Some((host, _port)) => host,
+        None => address,
+    };
+
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Connection to docker daemon.
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    Unix(UnixStream),
+}
+
+impl Connection for Stream {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            Stream::Tls(s) => Pin::new(s).poll_read(cx, buf),
+            Stream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            Stream::Tls(s) => Pin::new(s).poll_write(cx, buf),
+            Stream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            Stream::Tls(s) => Pin::new(s).poll_flush(cx),
+            Stream::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            Stream::Tls(s) => Pin::new(s).poll_shutdown(cx),
+            Stream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
@@ -1,25 +1,48 @@
 #![allow(non_snake_case)]
 
 use crate::auth::DockerConfig;
-use crate::client::Client;
-use anyhow::bail;
+use crate::client::{Client, PoolConfig};
+use crate::demux::{frame_stream, read_chunks, FrameStream, StdStream};
+use crate::error::{DockerError, Result};
+use crate::transport::Connector;
+use crate::util::AsyncStream;
+use bytes::{Bytes, BytesMut};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use hyper::body::HttpBody;
+use hyper::header::{CONNECTION, CONTENT_TYPE, UPGRADE};
+use hyper::{Body, Method, Request, Response};
 use log::debug;
 use serde::{Deserialize, Serialize};
-use url::Url;
-use tokio::io::AsyncWriteExt;
-use bytes::Bytes;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, WriteHalf};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Docker api handle. Cheap to clone, clones share connection pool.
+#[derive(Debug, Clone)]
 pub struct Api {
     client: Client,
     docker_config: DockerConfig,
+    http: hyper::Client<Connector>,
+    request_timeout: Option<Duration>,
 }
 
 impl Api {
     pub fn new(client: Client, docker_config: DockerConfig) -> Self {
+        Self::with_pool(client, docker_config, PoolConfig::default())
+    }
+
+    pub fn with_pool(client: Client, docker_config: DockerConfig, pool: PoolConfig) -> Self {
+        let http = hyper::Client::builder()
+            .pool_max_idle_per_host(pool.max_idle)
+            .pool_idle_timeout(pool.idle_timeout)
+            .build(client.connector());
+
         Api {
             client,
             docker_config,
+            http,
+            request_timeout: pool.request_timeout,
         }
     }
 
@@ -31,97 +54,319 @@ impl Api {
         Containers { api: self.clone() }
     }
 
+    pub fn system(&self) -> System {
+        System { api: self.clone() }
+    }
+
     pub fn container(&self, id: String) -> Container {
         Container {
             id,
             api: self.clone(),
         }
     }
+
+    async fn send(
+        &self,
+        operation: &'static str,
+        request: hyper::http::Result<Request<Body>>,
+    ) -> Result<Response<Body>> {
+        let request = request.map_err(|e| DockerError::protocol(operation, e))?;
+        let response = match self.request_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.http.request(request))
+                .await
+                .map_err(|_| DockerError::Timeout { operation, timeout })?,
+            None => self.http.request(request).await,
+        };
+
+        response.map_err(|e| DockerError::from_hyper(operation, e))
+    }
+
+    fn request(&self, method: Method, path: &str) -> hyper::http::request::Builder {
+        Request::builder()
+            .method(method)
+            .uri(self.client.uri(path))
+            .header(CONTENT_TYPE, "application/json")
+    }
+}
+
+/// Percent encodes everything except unreserved characters of RFC 3986.
+fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(char::from(byte))
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Whole seconds docker waits before killing, so that partial second still gives a grace period.
+fn stop_seconds(timeout: Duration) -> u64 {
+    match timeout.subsec_nanos() {
+        0 => timeout.as_secs(),
+        _ => timeout.as_secs() + 1,
+    }
+}
+
+async fn read_body(operation: &'static str, response: Response<Body>) -> Result<Bytes> {
+    hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| DockerError::protocol(operation, e))
 }
 
 pub struct Images {
     api: Api,
 }
 
-#[derive(Debug, PartialEq)]
-struct Image {
-    domain: String,
-    name: String,
-    tag: String,
-    digest: Option<String>,
+/// Normalized image reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub domain: String,
+    pub name: String,
+    pub tag: String,
+    /// Content digest, e.g. `sha256:...`. Takes precedence over tag when set.
+    pub digest: Option<String>,
 }
 
-impl From<Image> for ImageCreateArgs {
-    fn from(image: Image) -> Self {
-        if image.digest.is_some() {
-            unimplemented!("Image digest conversion to image create args is not implemented!");
+impl Image {
+    pub fn parse(tag: &str) -> Result<Image> {
+        normalize_image_tag(tag.to_string())
+    }
+
+    /// Fully qualified reference to create containers from,
+    /// pinned to digest when image has one.
+    pub fn reference(&self) -> String {
+        match &self.digest {
+            Some(digest) => format!("{}/{}@{}", self.domain, self.name, digest),
+            None => format!("{}/{}:{}", self.domain, self.name, self.tag),
         }
+    }
+}
 
+impl From<Image> for ImageCreateArgs {
+    fn from(image: Image) -> Self {
+        // Daemon accepts digest in place of tag
         Self {
             fromImage: format!("{}/{}", image.domain, image.name),
-            tag: image.tag,
+            tag: image.digest.unwrap_or(image.tag),
         }
     }
 }
 
-fn normalize_image_tag(tag: String) -> anyhow::Result<Image> {
-    let mut remaining_tag = tag;
+const DEFAULT_DOMAIN: &str = "docker.io";
+const LEGACY_DEFAULT_DOMAIN: &str = "index.docker.io";
+const OFFICIAL_REPOSITORY_PREFIX: &str = "library/";
+const DEFAULT_TAG: &str = "latest";
+const NAME_MAX_LENGTH: usize = 255;
+const TAG_MAX_LENGTH: usize = 128;
+
+/// Parses image reference by docker distribution reference grammar,
+/// normalizing it the same way docker cli does:
+/// `[domain[:port]/]path[:tag][@digest]`.
+fn normalize_image_tag(tag: String) -> Result<Image> {
+    let invalid = |reason: &str| DockerError::InvalidReference {
+        reference: tag.clone(),
+        reason: reason.to_string(),
+    };
 
-    if remaining_tag.is_empty() {
-        bail!("Docker image tag cannot be empty");
+    if tag.is_empty() {
+        return Err(invalid("Docker image tag cannot be empty"));
     }
 
-    let mut image_digest: Option<String> = None;
-    if remaining_tag.contains("@") {
-        let mut digest_iterator = remaining_tag.rsplit("@");
-        image_digest = digest_iterator.next().map(str::to_owned);
-        remaining_tag = digest_iterator.collect();
+    let (remaining, image_digest) = match tag.split_once('@') {
+        Some((remaining, digest)) => {
+            validate_digest(digest).map_err(invalid)?;
+            (remaining, Some(digest.to_string()))
+        }
+        None => (tag.as_str(), None),
+    };
+
+    // Tag colon can only come after the last slash, earlier one is a port
+    let path_start = remaining.rfind('/').map(|i| i + 1).unwrap_or(0);
+    let (name, image_tag) = match remaining[path_start..].rfind(':') {
+        Some(i) => {
+            let (name, image_tag) = remaining.split_at(path_start + i);
+            let image_tag = &image_tag[1..];
+            validate_tag(image_tag).map_err(invalid)?;
+            (name, image_tag)
+        }
+        None => (remaining, DEFAULT_TAG),
+    };
+
+    if name.len() > NAME_MAX_LENGTH {
+        return Err(invalid(
+            "Repository name must not be longer than 255 characters",
+        ));
     }
 
-    let image_tag: String;
-    if remaining_tag.contains(":") {
-        let mut tag_iterator = remaining_tag.rsplit(":");
-        image_tag = tag_iterator
-            .next()
-            .map(str::to_owned)
-            .expect("String contains ':' for sure");
-        remaining_tag = tag_iterator.collect();
-    } else {
-        image_tag = "latest".to_string();
+    let (image_domain, image_name) = split_domain(name);
+    validate_domain(image_domain).map_err(invalid)?;
+    validate_path(&image_name).map_err(invalid)?;
+
+    Ok(Image {
+        domain: image_domain.to_string(),
+        name: image_name,
+        tag: image_tag.to_string(),
+        digest: image_digest,
+    })
+}
+
+/// First path component is a domain only when it looks like one,
+/// otherwise image belongs to docker hub.
+fn split_domain(name: &str) -> (&str, String) {
+    let (domain, path) = match name.split_once('/') {
+        Some((first, rest))
+            if first.contains('.')
+                || first.contains(':')
+                || first == "localhost"
+                || first.chars().any(|c| c.is_ascii_uppercase()) =>
+        {
+            (first, rest)
+        }
+        _ => (DEFAULT_DOMAIN, name),
+    };
+
+    let domain = match domain {
+        LEGACY_DEFAULT_DOMAIN => DEFAULT_DOMAIN,
+        domain => domain,
+    };
+
+    let path = match domain == DEFAULT_DOMAIN && !path.contains('/') {
+        true => format!("{}{}", OFFICIAL_REPOSITORY_PREFIX, path),
+        false => path.to_string(),
+    };
+
+    (domain, path)
+}
+
+fn validate_domain(domain: &str) -> std::result::Result<(), &'static str> {
+    let (host, port) = match domain.rsplit_once(':') {
+        Some((host, port)) if !port.ends_with(']') => (host, Some(port)),
+        _ => (domain, None),
+    };
+
+    if let Some(port) = port {
+        if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("Registry port must be a number");
+        }
     }
 
-    if remaining_tag.matches("/").count() == 0 {
-        remaining_tag = format!("library/{}", remaining_tag);
+    if let Some(ipv6) = host.strip_prefix('[') {
+        let valid = ipv6
+            .strip_suffix(']')
+            .map(|ip| ip.parse::<std::net::Ipv6Addr>().is_ok())
+            .unwrap_or(false);
+        return match valid {
+            true => Ok(()),
+            false => Err("Invalid registry IPv6 address"),
+        };
     }
 
-    if remaining_tag.matches("/").count() == 1 {
-        remaining_tag = format!("docker.io/{}", remaining_tag);
+    let valid_component = |component: &str| {
+        !component.is_empty()
+            && !component.starts_with('-')
+            && !component.ends_with('-')
+            && component
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+
+    match host.split('.').all(valid_component) {
+        true => Ok(()),
+        false => Err("Invalid registry domain"),
     }
+}
 
-    if !remaining_tag.starts_with("http://") && !remaining_tag.starts_with("https://") {
-        remaining_tag = format!("https://{}", remaining_tag);
+fn validate_path(path: &str) -> std::result::Result<(), &'static str> {
+    if path.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err("Repository name must be lowercase");
     }
 
-    let url = Url::parse(&remaining_tag)?;
-    let image_domain = match url.domain() {
-        Some(d) => d.to_string(),
-        None => "docker.io".to_string(),
-    };
-    let mut image_name = match url.path().is_empty() {
-        true => bail!("Image name cannot be empty"),
-        false => url.path().to_string(),
+    for component in path.split('/') {
+        if !valid_path_component(component) {
+            return Err("Invalid repository name component");
+        }
+    }
+
+    Ok(())
+}
+
+/// `[a-z0-9]+` groups joined by `.`, `_`, `__` or any number of `-`.
+fn valid_path_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    let is_alphanumeric = |b: u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+    if bytes.is_empty() || !is_alphanumeric(bytes[0]) || !is_alphanumeric(bytes[bytes.len() - 1]) {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if is_alphanumeric(bytes[i]) {
+            i += 1;
+            continue;
+        }
+
+        let separator_start = i;
+        while i < bytes.len() && !is_alphanumeric(bytes[i]) {
+            i += 1;
+        }
+
+        let separator = &component[separator_start..i];
+        let valid_separator = separator == "."
+            || separator == "_"
+            || separator == "__"
+            || separator.bytes().all(|b| b == b'-');
+        if !valid_separator {
+            return false;
+        }
+    }
+
+    true
+}
+
+fn validate_tag(tag: &str) -> std::result::Result<(), &'static str> {
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let bytes = tag.as_bytes();
+
+    if bytes.is_empty() || bytes.len() > TAG_MAX_LENGTH {
+        return Err("Tag must be 1 to 128 characters long");
+    }
+
+    if !is_word(bytes[0])
+        || !bytes
+            .iter()
+            .all(|b| is_word(*b) || *b == b'.' || *b == b'-')
+    {
+        return Err("Tag may only contain letters, digits, '_', '.' and '-' and must not start with '.' or '-'");
+    }
+
+    Ok(())
+}
+
+fn validate_digest(digest: &str) -> std::result::Result<(), &'static str> {
+    let (algorithm, hex) = digest
+        .split_once(':')
+        .ok_or("Digest must be in algorithm:hex format")?;
+
+    let expected_length = match algorithm {
+        "sha256" => 64,
+        "sha384" => 96,
+        "sha512" => 128,
+        _ => return Err("Unsupported digest algorithm"),
     };
 
-    if image_name.starts_with("/") {
-        image_name.remove(0);
+    if hex.len() != expected_length
+        || !hex
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+    {
+        return Err("Invalid digest hex");
     }
 
-    Ok(Image {
-        domain: image_domain,
-        name: image_name,
-        tag: image_tag,
-        digest: image_digest,
-    })
+    Ok(())
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -130,63 +375,289 @@ pub struct ImageCreateArgs {
     pub tag: String,
 }
 
+/// Progress message streamed by daemon while image is pulled.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageCreateProgress {
+    /// Layer id, absent for messages about the whole image.
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub progressDetail: Option<ProgressDetail>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgressDetail {
+    /// Bytes downloaded or extracted so far.
+    #[serde(default)]
+    pub current: Option<u64>,
+    #[serde(default)]
+    pub total: Option<u64>,
+}
+
+impl fmt::Display for ImageCreateProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = &self.id {
+            write!(f, "{}: ", id)?;
+        }
+        write!(f, "{}", self.status.as_deref().unwrap_or_default())?;
+        if let Some(ProgressDetail {
+            current: Some(current),
+            total: Some(total),
+        }) = &self.progressDetail
+        {
+            write!(f, " {}/{}", current, total)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageInspect {
+    pub Id: String,
+    #[serde(default)]
+    pub RepoTags: Vec<String>,
+    /// `name@digest` entries of registries image was pulled from.
+    #[serde(default)]
+    pub RepoDigests: Vec<String>,
+}
+
+impl ImageInspect {
+    pub fn has_digest(&self, digest: &str) -> bool {
+        self.RepoDigests
+            .iter()
+            .any(|repo_digest| repo_digest.rsplit_once('@').map(|(_, d)| d) == Some(digest))
+    }
+}
+
+/// Image pull progress, yields an error and ends on first daemon error message.
+pub type ImageCreateStream = BoxStream<'static, Result<ImageCreateProgress>>;
+
+struct ProgressState {
+    reference: String,
+    body: Body,
+    buffer: BytesMut,
+    finished: bool,
+}
+
+fn progress_stream(reference: String, body: Body) -> ImageCreateStream {
+    let state = ProgressState {
+        reference,
+        body,
+        buffer: BytesMut::new(),
+        finished: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if state.finished {
+                return None;
+            }
+
+            let line = match state.buffer.iter().position(|b| *b == b'\n') {
+                Some(end) => Some(state.buffer.split_to(end + 1)),
+                None => match state.body.data().await {
+                    Some(Ok(chunk)) => {
+                        state.buffer.extend_from_slice(&chunk);
+                        None
+                    }
+                    Some(Err(e)) => {
+                        state.finished = true;
+                        return Some((Err(DockerError::protocol("create image", e)), state));
+                    }
+                    None => {
+                        state.finished = true;
+                        Some(state.buffer.split())
+                    }
+                },
+            };
+
+            let line = match line {
+                Some(line) if !line.iter().all(u8::is_ascii_whitespace) => line,
+                _ => continue,
+            };
+
+            let progress = match serde_json::from_slice::<ImageCreateProgress>(&line) {
+                Ok(progress) => progress,
+                Err(e) => {
+                    state.finished = true;
+                    return Some((Err(DockerError::protocol("create image", e)), state));
+                }
+            };
+
+            if let Some(message) = progress.error {
+                state.finished = true;
+                let error = DockerError::ImagePull {
+                    reference: state.reference.clone(),
+                    message,
+                };
+                return Some((Err(error), state));
+            }
+
+            return Some((Ok(progress), state));
+        }
+    })
+    .boxed()
+}
+
 impl Images {
-    pub async fn create(&self, body: ImageCreateArgs) -> anyhow::Result<()> {
+    /// Pulls image, resolving only after daemon reports pull completion.
+    pub async fn create(&self, body: ImageCreateArgs) -> Result<()> {
+        let mut progress = self.create_with_progress(body).await?;
+        while let Some(event) = progress.next().await {
+            event?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn create_with_progress(&self, body: ImageCreateArgs) -> Result<ImageCreateStream> {
+        const OPERATION: &str = "create image";
         let image = normalize_image_tag(body.fromImage.clone())?;
         let auth_entry = self.api.docker_config.auths.get(&image.domain);
 
-        let url = format!(
-            "{}/images/create?fromImage={}&tag={}",
-            self.api.client.host(),
-            body.fromImage,
-            body.tag
+        let path = format!(
+            "/images/create?fromImage={}&tag={}",
+            body.fromImage, body.tag
         );
-        let client = reqwest::Client::new();
-        let mut request = client.post(url).header("Content-Type", "application/json");
+        let mut request = self.api.request(Method::POST, &path);
 
         if let Some(auth) = auth_entry {
             debug!(
                 "Providing token auth for domain {} for {}",
                 &image.domain, &body.fromImage
             );
-            let json = serde_json::to_string(auth)?;
+            let json =
+                serde_json::to_string(auth).map_err(|e| DockerError::protocol(OPERATION, e))?;
             let base64 = base64::encode(json);
 
             request = request.header("X-Registry-Auth", base64);
         }
 
-        let response = request.send().await?;
-        let status = response.status();
-        if status != 200 {
-            bail!(
-                "Failed to create image: {} ({})",
-                response.text().await?,
-                status
-            );
+        let response = self
+            .api
+            .send(OPERATION, request.body(Body::empty()))
+            .await?;
+        let reference = format!("{}:{}", body.fromImage, body.tag);
+        if response.status() != 200 {
+            return Err(DockerError::from_response(OPERATION, Some(&reference), response).await);
         }
 
-        Ok(())
+        Ok(progress_stream(reference, response.into_body()))
     }
 
-    pub async fn pull(&self, tag: String) -> anyhow::Result<()> {
+    pub async fn pull(&self, tag: String) -> Result<()> {
         let image = normalize_image_tag(tag)?;
         self.create(image.into()).await?;
 
         Ok(())
     }
+
+    pub async fn pull_with_progress(&self, tag: String) -> Result<ImageCreateStream> {
+        let image = normalize_image_tag(tag)?;
+        self.create_with_progress(image.into()).await
+    }
+
+    /// Inspects local image by name, reference or id.
+    pub async fn inspect(&self, name: &str) -> Result<ImageInspect> {
+        const OPERATION: &str = "inspect image";
+        let path = format!("/images/{}/json", name);
+        let request = self.api.request(Method::GET, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
+
+        if response.status() != 200 {
+            return Err(DockerError::from_response(OPERATION, Some(name), response).await);
+        }
+
+        let body = read_body(OPERATION, response).await?;
+        serde_json::from_slice(&body).map_err(|e| DockerError::protocol(OPERATION, e))
+    }
 }
 
 pub struct Containers {
     api: Api,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ContainerCreateArgs {
     pub Image: String,
-    pub Cmd: Option<String>,
+    /// Image `CMD` is used when omitted, empty list runs entrypoint without arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub Cmd: Option<Vec<String>>,
+    /// Image `ENTRYPOINT` is used when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub Entrypoint: Option<Vec<String>>,
+    /// `NAME=value` pairs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub Env: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub WorkingDir: Option<String>,
+    /// `user`, `user:group` or numeric ids.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub User: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub Labels: HashMap<String, String>,
     pub AttachStdin: bool,
     pub OpenStdin: bool,
+    /// Close container stdin once attached client closes its stdin.
+    pub StdinOnce: bool,
     pub Tty: bool,
+    #[serde(default)]
+    pub HostConfig: HostConfig,
+}
+
+/// Resource limits of container, unset fields keep daemon defaults.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostConfig {
+    /// Memory limit in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub Memory: Option<i64>,
+    /// Memory plus swap limit in bytes, `-1` for unlimited swap.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub MemorySwap: Option<i64>,
+    /// CPU quota in units of 10<sup>-9</sup> CPUs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub NanoCpus: Option<i64>,
+    /// Relative CPU weight.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub CpuShares: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub PidsLimit: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub Ulimits: Vec<Ulimit>,
+    /// Size of `/dev/shm` in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ShmSize: Option<i64>,
+    /// Kernel capabilities, e.g. `NET_BIND_SERVICE` or `ALL`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub CapAdd: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub CapDrop: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ReadonlyRootfs: Option<bool>,
+    /// E.g. `no-new-privileges`, `seccomp=<profile json>` or `apparmor=<profile>`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub SecurityOpt: Vec<String>,
+    /// Mount point to tmpfs mount options, e.g. `/tmp` to `rw,size=64m`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub Tmpfs: HashMap<String, String>,
+    /// `none`, `bridge`, `host` or network name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub NetworkMode: Option<String>,
+    /// OCI runtime registered with daemon, e.g. `runsc`, daemon default when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub Runtime: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ulimit {
+    /// Limit name without `RLIMIT_` prefix, e.g. `nofile`.
+    pub Name: String,
+    pub Soft: i64,
+    pub Hard: i64,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -194,30 +665,73 @@ pub struct ContainerCreateResponse {
     pub Id: String,
 }
 
+/// Filters of [Containers::list].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ContainerListArgs {
+    /// Include stopped containers.
+    pub all: bool,
+    /// `key` or `key=value` labels container must have all of.
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerSummary {
+    pub Id: String,
+    #[serde(default)]
+    pub Names: Vec<String>,
+    #[serde(default)]
+    pub Image: String,
+    /// Unix timestamp in seconds.
+    #[serde(default)]
+    pub Created: i64,
+    #[serde(default)]
+    pub Labels: HashMap<String, String>,
+    /// `created`, `running`, `paused`, `restarting`, `removing`, `exited` or `dead`.
+    #[serde(default)]
+    pub State: String,
+    /// Human readable status, e.g. `Exited (0) 5 seconds ago`.
+    #[serde(default)]
+    pub Status: String,
+}
+
 impl Containers {
-    pub async fn create(&self, body: ContainerCreateArgs) -> anyhow::Result<Container> {
-        let url = format!("{}/containers/create", self.api.client.host());
-        let raw_body = serde_json::to_string(&body)?;
-        let client = reqwest::Client::new();
-        let response = client
-            .post(url)
-            .body(raw_body)
-            .header("Content-Type", "application/json")
-            .send()
-            .await?;
+    pub async fn list(&self, opts: ContainerListArgs) -> Result<Vec<ContainerSummary>> {
+        const OPERATION: &str = "list containers";
+        let filters = serde_json::json!({ "label": opts.labels }).to_string();
+        let path = format!(
+            "/containers/json?all={}&filters={}",
+            u8::from(opts.all),
+            encode_query_value(&filters)
+        );
+        let request = self.api.request(Method::GET, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
 
-        let status = response.status();
-        if status != 201 {
-            bail!(
-                "Failed to create container: {} ({})",
-                response.text().await?,
-                status
-            );
+        if response.status() != 200 {
+            return Err(DockerError::from_response(OPERATION, None, response).await);
+        }
+
+        let body = read_body(OPERATION, response).await?;
+        serde_json::from_slice(&body).map_err(|e| DockerError::protocol(OPERATION, e))
+    }
+
+    pub async fn create(&self, body: ContainerCreateArgs) -> Result<Container> {
+        const OPERATION: &str = "create container";
+        let raw_body =
+            serde_json::to_string(&body).map_err(|e| DockerError::protocol(OPERATION, e))?;
+        let request = self
+            .api
+            .request(Method::POST, "/containers/create")
+            .body(Body::from(raw_body));
+        let response = self.api.send(OPERATION, request).await?;
+
+        if response.status() != 201 {
+            return Err(DockerError::from_response(OPERATION, Some(&body.Image), response).await);
         }
 
-        let response_text = response.text().await?;
+        let response_body = read_body(OPERATION, response).await?;
         let container_create_response: ContainerCreateResponse =
-            serde_json::from_str(&response_text)?;
+            serde_json::from_slice(&response_body)
+                .map_err(|e| DockerError::protocol(OPERATION, e))?;
 
         Ok(Container {
             id: container_create_response.Id,
@@ -232,240 +746,689 @@ pub struct Container {
     api: Api,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerWaitResponse {
+    /// Container process exit code.
+    pub StatusCode: i64,
+    /// Set when daemon failed to wait for container.
+    #[serde(default)]
+    pub Error: Option<ContainerWaitError>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerWaitError {
+    #[serde(default)]
+    pub Message: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerInspect {
+    pub Id: String,
+    #[serde(default)]
+    pub Name: String,
+    #[serde(default)]
+    pub Created: String,
+    #[serde(default)]
+    pub Image: String,
+    pub State: ContainerState,
+    #[serde(default)]
+    pub NetworkSettings: NetworkSettings,
+}
+
+impl ContainerInspect {
+    /// Address of container on `network`, on any network it is attached to when `None`.
+    pub fn ip_address(&self, network: Option<&str>) -> Option<&str> {
+        let settings = &self.NetworkSettings;
+        let address = match network {
+            Some(network) => settings.Networks.get(network).map(|n| n.IPAddress.as_str()),
+            None => std::iter::once(settings.IPAddress.as_str())
+                .chain(settings.Networks.values().map(|n| n.IPAddress.as_str()))
+                .find(|address| !address.is_empty()),
+        };
+        address.filter(|address| !address.is_empty())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkSettings {
+    /// Address on default bridge network.
+    #[serde(default)]
+    pub IPAddress: String,
+    /// Keyed by network name.
+    #[serde(default)]
+    pub Networks: HashMap<String, EndpointSettings>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EndpointSettings {
+    #[serde(default)]
+    pub IPAddress: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerState {
+    /// One of `created`, `running`, `paused`, `restarting`, `removing`, `exited`, `dead`.
+    #[serde(default)]
+    pub Status: String,
+    #[serde(default)]
+    pub Running: bool,
+    #[serde(default)]
+    pub OOMKilled: bool,
+    #[serde(default)]
+    pub ExitCode: i64,
+    #[serde(default)]
+    pub Error: String,
+    /// RFC 3339 timestamp, `0001-01-01T00:00:00Z` when never started.
+    #[serde(default)]
+    pub StartedAt: String,
+    #[serde(default)]
+    pub FinishedAt: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ContainerLogs {
+    pub stdout: Bytes,
+    pub stderr: Bytes,
+}
+
+impl ContainerLogs {
+    /// Collects demultiplexed output until stream ends.
+    pub async fn collect(mut frames: FrameStream) -> Result<ContainerLogs> {
+        let mut stdout = BytesMut::new();
+        let mut stderr = BytesMut::new();
+
+        while let Some(frame) = frames.next().await {
+            let frame = frame?;
+            match frame.stream {
+                StdStream::Stdout => stdout.extend_from_slice(&frame.data),
+                StdStream::Stderr => stderr.extend_from_slice(&frame.data),
+                StdStream::Stdin => {}
+            }
+        }
+
+        Ok(ContainerLogs {
+            stdout: stdout.freeze(),
+            stderr: stderr.freeze(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ContainerAttachArgs {
     pub stream: bool,
     pub stdin: bool,
+    pub stdout: bool,
+    pub stderr: bool,
+    /// Replay output produced before attach.
+    pub logs: bool,
+}
+
+impl ContainerAttachArgs {
+    fn query(&self) -> String {
+        format!(
+            "stream={}&stdin={}&stdout={}&stderr={}&logs={}",
+            u8::from(self.stream),
+            u8::from(self.stdin),
+            u8::from(self.stdout),
+            u8::from(self.stderr),
+            u8::from(self.logs)
+        )
+    }
+}
+
+/// Write half of hijacked attach connection.
+/// Shutting it down closes container stdin if it was created with `StdinOnce`.
+pub type AttachStdin = WriteHalf<Box<dyn AsyncStream + Send + Unpin>>;
+
+/// Hijacked connection to container started by [Container::attach].
+pub struct ContainerAttach {
+    pub stdin: AttachStdin,
+    /// Demultiplexed output, ends when container exits.
+    pub output: FrameStream,
 }
 
 impl Container {
-    pub async fn start(&self) -> anyhow::Result<()> {
-        let url = format!("{}/containers/{}/start", self.api.client.host(), self.id);
-        let client = reqwest::Client::new();
-        let response = client
-            .post(url)
-            .header("Content-Type", "application/json")
-            .send()
-            .await?;
+    pub fn id(&self) -> &str {
+        &self.id
+    }
 
-        let status = response.status();
-        if status != 204 {
-            bail!(
-                "Failed to start container: {} ({})",
-                response.text().await?,
-                status
-            );
+    pub async fn start(&self) -> Result<()> {
+        const OPERATION: &str = "start container";
+        let path = format!("/containers/{}/start", self.id);
+        let request = self.api.request(Method::POST, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
+
+        if response.status() != 204 {
+            return Err(DockerError::from_response(OPERATION, Some(&self.id), response).await);
         }
 
         // TODO: Check body
         Ok(())
     }
 
-    pub async fn pause(&self) -> anyhow::Result<()> {
-        let url = format!("{}/containers/{}/pause", self.api.client.host(), self.id);
-        let client = reqwest::Client::new();
-        let response = client
-            .post(url)
-            .header("Content-Type", "application/json")
-            .send()
-            .await?;
+    pub async fn pause(&self) -> Result<()> {
+        const OPERATION: &str = "pause container";
+        let path = format!("/containers/{}/pause", self.id);
+        let request = self.api.request(Method::POST, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
 
-        let status = response.status();
-        if status != 204 {
-            bail!(
-                "Failed to pause container: {} ({})",
-                response.text().await?,
-                status
-            );
+        if response.status() != 204 {
+            return Err(DockerError::from_response(OPERATION, Some(&self.id), response).await);
         }
 
         // TODO: Check body
         Ok(())
     }
 
-    /// Wait for container to stop
-    pub async fn wait(&self) -> anyhow::Result<()> {
-        let url = format!("{}/containers/{}/wait", self.api.client.host(), self.id);
-        let client = reqwest::Client::new();
-        let response = client
-            .post(url)
-            .header("Content-Type", "application/json")
-            .send()
-            .await?;
+    /// Sends signal like `SIGTERM` or `SIGKILL` to container main process.
+    pub async fn kill(&self, signal: &str) -> Result<()> {
+        const OPERATION: &str = "kill container";
+        let path = format!("/containers/{}/kill?signal={}", self.id, signal);
+        let request = self.api.request(Method::POST, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
 
-        let status = response.status();
-        if status != 200 {
-            bail!(
-                "Failed to wait for container: {} ({})",
-                response.text().await?,
-                status
-            );
+        if response.status() != 204 {
+            return Err(DockerError::from_response(OPERATION, Some(&self.id), response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Sends `SIGTERM`, then `SIGKILL` if container is still running after `timeout`,
+    /// rounded up to whole seconds. Stopping already stopped container is not an error.
+    pub async fn stop(&self, timeout: Duration) -> Result<()> {
+        const OPERATION: &str = "stop container";
+        let path = format!("/containers/{}/stop?t={}", self.id, stop_seconds(timeout));
+        let request = self.api.request(Method::POST, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
+
+        if response.status() != 204 && response.status() != 304 {
+            return Err(DockerError::from_response(OPERATION, Some(&self.id), response).await);
         }
 
-        // TODO: Check body
         Ok(())
     }
 
     /// Wait for container to stop
-    pub async fn delete(&self) -> anyhow::Result<()> {
-        let url = format!("{}/containers/{}?force=1", self.api.client.host(), self.id);
-        let client = reqwest::Client::new();
-        let response = client
-            .delete(url)
-            .header("Content-Type", "application/json")
-            .send()
-            .await?;
+    pub async fn wait(&self) -> Result<ContainerWaitResponse> {
+        const OPERATION: &str = "wait for container";
+        let path = format!("/containers/{}/wait", self.id);
+        let request = self.api.request(Method::POST, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
+
+        if response.status() != 200 {
+            return Err(DockerError::from_response(OPERATION, Some(&self.id), response).await);
+        }
 
-        let status = response.status();
-        if status != 204 {
-            bail!(
-                "Failed to delete {} container: {} ({})",
-                self.id,
-                response.text().await?,
-                status
-            );
+        let body = read_body(OPERATION, response).await?;
+        serde_json::from_slice(&body).map_err(|e| DockerError::protocol(OPERATION, e))
+    }
+
+    pub async fn inspect(&self) -> Result<ContainerInspect> {
+        const OPERATION: &str = "inspect container";
+        let path = format!("/containers/{}/json", self.id);
+        let request = self.api.request(Method::GET, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
+
+        if response.status() != 200 {
+            return Err(DockerError::from_response(OPERATION, Some(&self.id), response).await);
+        }
+
+        let body = read_body(OPERATION, response).await?;
+        serde_json::from_slice(&body).map_err(|e| DockerError::protocol(OPERATION, e))
+    }
+
+    /// Wait for container to stop
+    pub async fn delete(&self) -> Result<()> {
+        const OPERATION: &str = "delete container";
+        let path = format!("/containers/{}?force=1", self.id);
+        let request = self.api.request(Method::DELETE, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
+
+        if response.status() != 204 {
+            return Err(DockerError::from_response(OPERATION, Some(&self.id), response).await);
         }
 
         // TODO: Check body
         Ok(())
     }
 
-    pub async fn logs(&self) -> anyhow::Result<String> {
-        let url = format!(
-            "{}/containers/{}/logs?stdout=true",
-            self.api.client.host(),
-            self.id
-        );
-        let client = reqwest::Client::new();
-        let response = client.get(url).send().await?;
-
-        let status = response.status();
-        let body = response.text().await?;
-        if status != 200 {
-            bail!(
-                "Failed to get {} container logs: {} ({})",
-                self.id,
-                body,
-                status
-            );
+    /// Collects stdout and stderr of container created without tty.
+    pub async fn logs(&self) -> Result<ContainerLogs> {
+        ContainerLogs::collect(self.logs_stream().await?).await
+    }
+
+    /// Demultiplexed stdout and stderr of container created without tty.
+    pub async fn logs_stream(&self) -> Result<FrameStream> {
+        const OPERATION: &str = "get container logs";
+        let path = format!("/containers/{}/logs?stdout=1&stderr=1", self.id);
+        let request = self.api.request(Method::GET, &path).body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
+
+        if response.status() != 200 {
+            return Err(DockerError::from_response(OPERATION, Some(&self.id), response).await);
         }
 
-        // TODO: Check body
-        Ok(body)
+        Ok(frame_stream(OPERATION, response.into_body()))
     }
 
-    pub async fn send_to_stdin(&self, input: Bytes) -> anyhow::Result<()> {
-        let url = format!("{}/v1.37/containers/{}/attach?stream=1&stdin=1&stdout=1&stderr=1&logs=1", self.api.client.host(), self.id);
-        dbg!(&url);
-        let client = hyper::Client::new();
-        let request = hyper::Request::builder()
-            .method("POST")
-            .uri(url)
-            .header(hyper::header::UPGRADE, "tcp")
-            .body(hyper::Body::empty())
-            .unwrap();
+    /// Hijacks connection to container stdin and output.
+    ///
+    /// Attach before [start](Container::start) so no output is lost,
+    /// container must be created without tty.
+    pub async fn attach(&self, opts: ContainerAttachArgs) -> Result<ContainerAttach> {
+        const OPERATION: &str = "attach to container";
+        let path = format!("/containers/{}/attach?{}", self.id, opts.query());
+        let request = self
+            .api
+            .request(Method::POST, &path)
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "tcp")
+            .body(Body::empty());
 
         debug!("Attach request to docker container");
-        let response = client
-            .request(request)
-            .await?;
+        let response = self.api.send(OPERATION, request).await?;
 
-        let status = response.status();
-        // TODO: Check body
-        if status != 101 {
-            let body_bytes = hyper::body::to_bytes(response.into_body()).await?;
-            let string_body = String::from_utf8_lossy(&body_bytes);
-
-            bail!(
-                "Failed to send stdin input to container: {:?} ({})",
-                string_body,
-                status
-            );
+        if response.status() != 101 {
+            return Err(DockerError::from_response(OPERATION, Some(&self.id), response).await);
         }
 
         debug!("Upgrading connection to tcp");
-        let mut upgraded_stream = hyper::upgrade::on(response).await?;
+        let upgraded = hyper::upgrade::on(response)
+            .await
+            .map_err(|e| DockerError::protocol(OPERATION, e))?;
+        let stream: Box<dyn AsyncStream + Send + Unpin> = Box::new(upgraded);
+        let (output, stdin) = tokio::io::split(stream);
+
+        Ok(ContainerAttach {
+            stdin,
+            output: frame_stream(OPERATION, read_chunks(output)),
+        })
+    }
+
+    /// Writes input to container stdin and closes it.
+    pub async fn send_to_stdin(&self, input: Bytes) -> Result<()> {
+        const OPERATION: &str = "attach to container";
+        let opts = ContainerAttachArgs {
+            stream: true,
+            stdin: true,
+            stdout: false,
+            stderr: false,
+            logs: false,
+        };
+        let mut stdin = self.attach(opts).await?.stdin;
+
         debug!("Sending stdin input to docker container");
-        upgraded_stream.write_all(&input).await?;
+        stdin
+            .write_all(&input)
+            .await
+            .map_err(|e| DockerError::protocol(OPERATION, e))?;
+        stdin
+            .shutdown()
+            .await
+            .map_err(|e| DockerError::protocol(OPERATION, e))?;
         debug!("Stdin input sent");
 
         Ok(())
     }
 }
 
+pub struct System {
+    api: Api,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SystemInfo {
+    #[serde(default)]
+    pub ServerVersion: String,
+    /// OCI runtimes registered with daemon by name.
+    #[serde(default)]
+    pub Runtimes: HashMap<String, RuntimeInfo>,
+    #[serde(default)]
+    pub DefaultRuntime: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuntimeInfo {
+    #[serde(default)]
+    pub path: String,
+}
+
+impl System {
+    pub async fn info(&self) -> Result<SystemInfo> {
+        const OPERATION: &str = "get system info";
+        let request = self.api.request(Method::GET, "/info").body(Body::empty());
+        let response = self.api.send(OPERATION, request).await?;
+
+        if response.status() != 200 {
+            return Err(DockerError::from_response(OPERATION, None, response).await);
+        }
+
+        let body = read_body(OPERATION, response).await?;
+        serde_json::from_slice(&body).map_err(|e| DockerError::protocol(OPERATION, e))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::normalize_image_tag;
-    use super::{Image, ImageCreateArgs};
+    use super::{encode_query_value, normalize_image_tag, progress_stream, stop_seconds};
+    use super::{ContainerCreateArgs, ContainerInspect, ContainerWaitResponse, HostConfig, Ulimit};
+    use super::{Image, ImageCreateArgs, ImageCreateProgress, ProgressDetail};
+    use crate::error::DockerError;
+    use futures_util::StreamExt;
+    use hyper::Body;
+    use std::collections::HashMap;
+    use std::time::Duration;
 
     #[test]
     fn test_normalize_image_tag() {
+        let digest = "sha256:8c5aeeb6a5f3ba4883347d3747a7249f491766ca1caa47e5da5dfcf6b9b717c0";
+        let image = |domain: &str, name: &str, tag: &str, digest: Option<&str>| Image {
+            domain: domain.into(),
+            name: name.into(),
+            tag: tag.into(),
+            digest: digest.map(str::to_owned),
+        };
+
+        let valid = [
+            (
+                format!("ghcr.io/library/hello-world:alpine@{}", digest),
+                image("ghcr.io", "library/hello-world", "alpine", Some(digest)),
+            ),
+            (
+                format!("ghcr.io/library/hello-world@{}", digest),
+                image("ghcr.io", "library/hello-world", "latest", Some(digest)),
+            ),
+            (
+                "ghcr.io/library/hello-world:alpine".into(),
+                image("ghcr.io", "library/hello-world", "alpine", None),
+            ),
+            (
+                "ghcr.io/library/hello-world".into(),
+                image("ghcr.io", "library/hello-world", "latest", None),
+            ),
+            (
+                "library/hello-world".into(),
+                image("docker.io", "library/hello-world", "latest", None),
+            ),
+            (
+                "hello-world".into(),
+                image("docker.io", "library/hello-world", "latest", None),
+            ),
+            (
+                "hello-world:1.0".into(),
+                image("docker.io", "library/hello-world", "1.0", None),
+            ),
+            (
+                "fedcomp/hello-world".into(),
+                image("docker.io", "fedcomp/hello-world", "latest", None),
+            ),
+            (
+                "index.docker.io/hello-world".into(),
+                image("docker.io", "library/hello-world", "latest", None),
+            ),
+            (
+                "localhost:5000/team/fn".into(),
+                image("localhost:5000", "team/fn", "latest", None),
+            ),
+            (
+                "localhost:5000/team/fn:v1.2".into(),
+                image("localhost:5000", "team/fn", "v1.2", None),
+            ),
+            (
+                "localhost/fn".into(),
+                image("localhost", "fn", "latest", None),
+            ),
+            (
+                "myregistry.local/fn".into(),
+                image("myregistry.local", "fn", "latest", None),
+            ),
+            (
+                "registry.example.com:443/a/b/c/fn_name__x.y-z:tag".into(),
+                image(
+                    "registry.example.com:443",
+                    "a/b/c/fn_name__x.y-z",
+                    "tag",
+                    None,
+                ),
+            ),
+            (
+                "[::1]:5000/fn".into(),
+                image("[::1]:5000", "fn", "latest", None),
+            ),
+            (
+                "Registry/fn".into(),
+                image("Registry", "fn", "latest", None),
+            ),
+        ];
+
+        for (input, expected) in valid {
+            assert_eq!(
+                normalize_image_tag(input.clone()).unwrap(),
+                expected,
+                "{}",
+                input
+            );
+        }
+
+        let invalid = [
+            "",
+            "Hello-World",
+            "ghcr.io/Fedcomp/fn",
+            "fn:",
+            "fn:-tag",
+            "fn:tag/x",
+            "fn@sha256:1234",
+            "fn@md5:8c5aeeb6a5f3ba4883347d3747a7249f",
+            "localhost:port/fn",
+            "-registry.local/fn",
+            "registry.local/fn_-x",
+            "registry.local//fn",
+            "registry.local/fn/",
+        ];
+
+        for input in invalid {
+            assert!(normalize_image_tag(input.into()).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_image_create_args_from_image() {
         assert_eq!(
-            normalize_image_tag("ghcr.io/library/hello-world:alpine@1234".into()).unwrap(),
-            Image {
+            ImageCreateArgs::from(Image {
                 domain: "ghcr.io".into(),
                 name: "library/hello-world".into(),
                 tag: "alpine".into(),
-                digest: Some("1234".into()),
+                digest: None,
+            }),
+            ImageCreateArgs {
+                fromImage: "ghcr.io/library/hello-world".into(),
+                tag: "alpine".into(),
             }
         );
 
         assert_eq!(
-            normalize_image_tag("ghcr.io/library/hello-world:alpine".into()).unwrap(),
-            Image {
+            ImageCreateArgs::from(Image {
                 domain: "ghcr.io".into(),
                 name: "library/hello-world".into(),
                 tag: "alpine".into(),
-                digest: None,
+                digest: Some("sha256:1234".into()),
+            }),
+            ImageCreateArgs {
+                fromImage: "ghcr.io/library/hello-world".into(),
+                tag: "sha256:1234".into(),
             }
         );
+    }
 
+    #[test]
+    fn test_image_reference() {
+        let digest = "sha256:8c5aeeb6a5f3ba4883347d3747a7249f491766ca1caa47e5da5dfcf6b9b717c0";
+        let image =
+            Image::parse(&format!("ghcr.io/fedcomp/hello-world:latest@{}", digest)).unwrap();
         assert_eq!(
-            normalize_image_tag("ghcr.io/library/hello-world".into()).unwrap(),
-            Image {
-                domain: "ghcr.io".into(),
-                name: "library/hello-world".into(),
-                tag: "latest".into(),
-                digest: None,
-            }
+            image.reference(),
+            format!("ghcr.io/fedcomp/hello-world@{}", digest)
         );
 
+        let image = Image::parse("localhost:5000/team/fn:v1").unwrap();
+        assert_eq!(image.reference(), "localhost:5000/team/fn:v1");
+
+        let image = Image::parse("hello-world").unwrap();
+        assert_eq!(image.reference(), "docker.io/library/hello-world:latest");
+    }
+
+    #[tokio::test]
+    async fn test_progress_stream() {
+        let chunks: Vec<Result<&str, std::io::Error>> = vec![
+            Ok("{\"status\":\"Pulling from fedcomp/hello-world\",\"id\":\"latest\"}\n{\"sta"),
+            Ok("tus\":\"Downloading\",\"progressDetail\":{\"current\":10,\"total\":100},\"id\":\"2db2\"}\n\n"),
+            Ok("{\"errorDetail\":{\"message\":\"manifest unknown\"},\"error\":\"manifest unknown\"}\n"),
+            Ok("{\"status\":\"never reached\"}\n"),
+        ];
+        let body = Body::wrap_stream(futures_util::stream::iter(chunks));
+        let events: Vec<_> = progress_stream("ghcr.io/fedcomp/hello-world:latest".into(), body)
+            .collect()
+            .await;
+
+        assert_eq!(events.len(), 3);
         assert_eq!(
-            normalize_image_tag("library/hello-world".into()).unwrap(),
-            Image {
-                domain: "docker.io".into(),
-                name: "library/hello-world".into(),
-                tag: "latest".into(),
-                digest: None,
+            events[0].as_ref().unwrap(),
+            &ImageCreateProgress {
+                id: Some("latest".into()),
+                status: Some("Pulling from fedcomp/hello-world".into()),
+                ..Default::default()
             }
         );
+        assert_eq!(
+            events[1].as_ref().unwrap().progressDetail,
+            Some(ProgressDetail {
+                current: Some(10),
+                total: Some(100),
+            })
+        );
+        assert!(matches!(
+            &events[2],
+            Err(DockerError::ImagePull { message, .. }) if message == "manifest unknown"
+        ));
+    }
 
+    #[test]
+    fn test_container_exit_state() {
+        let wait: ContainerWaitResponse =
+            serde_json::from_str(r#"{"Error":null,"StatusCode":137}"#).unwrap();
+        assert_eq!(wait.StatusCode, 137);
+        assert_eq!(wait.Error, None);
+
+        let inspect: ContainerInspect = serde_json::from_str(
+            r#"{
+                "Id": "abc",
+                "Name": "/fn",
+                "State": {
+                    "Status": "exited",
+                    "Running": false,
+                    "OOMKilled": true,
+                    "ExitCode": 137,
+                    "Error": "",
+                    "StartedAt": "2022-05-01T10:00:00.000000000Z",
+                    "FinishedAt": "2022-05-01T10:00:01.000000000Z"
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(inspect.State.OOMKilled);
+        assert_eq!(inspect.State.ExitCode, 137);
+        assert_eq!(inspect.State.Status, "exited");
+    }
+
+    #[test]
+    fn test_container_ip_address() {
+        let inspect: ContainerInspect = serde_json::from_str(
+            r#"{
+                "Id": "abc",
+                "State": {"Status": "running", "Running": true},
+                "NetworkSettings": {
+                    "IPAddress": "",
+                    "Networks": {
+                        "functions": {"IPAddress": "172.20.0.3"},
+                        "none": {"IPAddress": ""}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(inspect.ip_address(Some("functions")), Some("172.20.0.3"));
+        assert_eq!(inspect.ip_address(Some("none")), None);
+        assert_eq!(inspect.ip_address(Some("bridge")), None);
+        assert_eq!(inspect.ip_address(None), Some("172.20.0.3"));
+    }
+
+    #[test]
+    fn test_container_create_args() {
+        let args = ContainerCreateArgs {
+            Image: "alpine:latest".to_string(),
+            Cmd: Some(vec!["cat".to_string()]),
+            Entrypoint: None,
+            Env: vec![],
+            WorkingDir: None,
+            User: None,
+            Labels: HashMap::new(),
+            AttachStdin: false,
+            OpenStdin: false,
+            StdinOnce: false,
+            Tty: false,
+            HostConfig: HostConfig {
+                Memory: Some(64 * 1024 * 1024),
+                PidsLimit: Some(32),
+                Ulimits: vec![Ulimit {
+                    Name: "nofile".to_string(),
+                    Soft: 256,
+                    Hard: 512,
+                }],
+                ..HostConfig::default()
+            },
+        };
+
+        let json = serde_json::to_value(&args).unwrap();
+        assert_eq!(json["Cmd"], serde_json::json!(["cat"]));
+        assert!(json.get("Env").is_none());
         assert_eq!(
-            normalize_image_tag("hello-world".into()).unwrap(),
-            Image {
-                domain: "docker.io".into(),
-                name: "library/hello-world".into(),
-                tag: "latest".into(),
-                digest: None,
-            }
+            json["HostConfig"],
+            serde_json::json!({
+                "Memory": 67108864,
+                "PidsLimit": 32,
+                "Ulimits": [{"Name": "nofile", "Soft": 256, "Hard": 512}],
+            })
         );
 
-        assert!(normalize_image_tag("".into()).is_err());
+        let json = serde_json::to_value(ContainerCreateArgs {
+            Cmd: None,
+            ..args.clone()
+        })
+        .unwrap();
+        assert!(json.get("Cmd").is_none());
+        let json = serde_json::to_value(ContainerCreateArgs {
+            Cmd: Some(vec![]),
+            ..args
+        })
+        .unwrap();
+        assert_eq!(json["Cmd"], serde_json::json!([]));
     }
 
     #[test]
-    fn test_image_create_args_from_image() {
+    fn test_encode_query_value() {
+        assert_eq!(encode_query_value("plain-value_1.0~"), "plain-value_1.0~");
         assert_eq!(
-            ImageCreateArgs::from(Image {
-                domain: "ghcr.io".into(),
-                name: "library/hello-world".into(),
-                tag: "alpine".into(),
-                digest: None,
-            }),
-            ImageCreateArgs {
-                fromImage: "ghcr.io/library/hello-world".into(),
-                tag: "alpine".into(),
-            }
+            encode_query_value(r#"{"label":["a=b c"]}"#),
+            "%7B%22label%22%3A%5B%22a%3Db%20c%22%5D%7D"
         );
     }
+
+    #[test]
+    fn test_stop_seconds() {
+        assert_eq!(stop_seconds(Duration::ZERO), 0);
+        assert_eq!(stop_seconds(Duration::from_millis(500)), 1);
+        assert_eq!(stop_seconds(Duration::from_secs(2)), 2);
+        assert_eq!(stop_seconds(Duration::from_millis(2001)), 3);
+    }
 }
@@ -0,0 +1,271 @@
+use crate::config::ReaperConfig;
+use crate::invoke::POOL_LABEL;
+use log::{debug, info, warn};
+use simple_faas_docker::error::{DockerError, Result as DockerResult};
+use simple_faas_docker::v1_37::{Api as DockerApi, Container, ContainerListArgs, ContainerSummary};
+use std::ops::Deref;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+/// Label with name of the function container runs.
+pub const FUNCTION_LABEL: &str = "simple-faas.function";
+/// Label with id of the single call container serves.
+pub const INVOCATION_LABEL: &str = "simple-faas.invocation";
+/// Label with [Config::instance_id](crate::config::Config) of gateway that created container.
+pub const INSTANCE_LABEL: &str = "simple-faas.instance";
+/// Stopped containers are left to their invocation to remove for this long.
+const STOPPED_GRACE: Duration = Duration::from_secs(60);
+/// How many times removal of a container is attempted.
+const DELETE_ATTEMPTS: u32 = 5;
+
+/// Handle of background task removing function containers.
+///
+/// Removal runs outside of request handler, so it still happens
+/// when handler returns early with error or client disconnects.
+#[derive(Debug, Clone)]
+pub struct Cleanup {
+    sender: mpsc::UnboundedSender<String>,
+}
+
+impl Cleanup {
+    pub fn spawn(api: DockerApi) -> Cleanup {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(delete_containers(api, receiver));
+
+        Cleanup { sender }
+    }
+
+    /// Schedules container removal once returned guard is dropped.
+    pub fn guard(&self, container: Container) -> ContainerGuard {
+        ContainerGuard {
+            container,
+            cleanup: self.clone(),
+        }
+    }
+
+    pub fn delete(&self, id: String) {
+        if self.sender.send(id).is_err() {
+            warn!("Container cleanup task is gone");
+        }
+    }
+}
+
+/// Container removed when guard goes out of scope.
+pub struct ContainerGuard {
+    container: Container,
+    cleanup: Cleanup,
+}
+
+impl Deref for ContainerGuard {
+    type Target = Container;
+
+    fn deref(&self) -> &Container {
+        &self.container
+    }
+}
+
+impl Drop for ContainerGuard {
+    fn drop(&mut self) {
+        self.cleanup.delete(self.container.id().to_string());
+    }
+}
+
+async fn delete_containers(api: DockerApi, mut receiver: mpsc::UnboundedReceiver<String>) {
+    while let Some(id) = receiver.recv().await {
+        tokio::spawn(delete_container(api.clone(), id));
+    }
+}
+
+async fn delete_container(api: DockerApi, id: String) {
+    let container = api.container(id);
+    for attempt in 1..=DELETE_ATTEMPTS {
+        match container.delete().await {
+            Ok(()) => {
+                debug!("Deleted container {}", container.id());
+                return;
+            }
+            // Already gone or removal in progress
+            Err(DockerError::NotFound(_)) | Err(DockerError::Conflict(_)) => return,
+            Err(e) => {
+                warn!(
+                    "Failed to delete container {} ({}/{}): {}",
+                    container.id(),
+                    attempt,
+                    DELETE_ATTEMPTS,
+                    e
+                );
+                tokio::time::sleep(Duration::from_secs(u64::from(attempt))).await;
+            }
+        }
+    }
+}
+
+/// Periodically removes containers of this gateway that outlived their invocation.
+pub fn spawn_reaper(api: DockerApi, cleanup: Cleanup, instance_id: String, config: ReaperConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        // First tick completes immediately, startup reap already ran
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            let reaped = reap(&api, &cleanup, &instance_id, config.max_age, Reap::Periodic);
+            if let Err(e) = reaped.await {
+                warn!("Failed to reap containers: {}", e);
+            }
+        }
+    });
+}
+
+/// Which containers [reap] considers left behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reap {
+    /// Ones not running or older than max age. Running ones may still belong
+    /// to another gateway started with the same instance id.
+    Startup,
+    /// Ones older than max age or stopped for a while.
+    Periodic,
+}
+
+/// Removes containers of this gateway instance and returns how many.
+/// Running warm pool containers are never removed, their pool does it.
+pub async fn reap(
+    api: &DockerApi,
+    cleanup: &Cleanup,
+    instance_id: &str,
+    max_age: Duration,
+    mode: Reap,
+) -> DockerResult<usize> {
+    let opts = ContainerListArgs {
+        all: true,
+        labels: vec![format!("{}={}", INSTANCE_LABEL, instance_id)],
+    };
+    let containers = api.containers().list(opts).await?;
+    let now = SystemTime::now();
+
+    let mut reaped = 0;
+    for summary in containers {
+        let orphaned = match classify(&summary, now, max_age, mode) {
+            Verdict::Keep => false,
+            Verdict::Remove => true,
+            Verdict::IfStoppedLongAgo => stopped_long_ago(api, &summary, now).await?,
+        };
+        if !orphaned {
+            continue;
+        }
+
+        info!(
+            "Reaping container {} of function {} ({})",
+            summary.Id,
+            summary
+                .Labels
+                .get(FUNCTION_LABEL)
+                .map_or("unknown", String::as_str),
+            summary.Status
+        );
+        cleanup.delete(summary.Id);
+        reaped += 1;
+    }
+
+    Ok(reaped)
+}
+
+/// What [reap] does with a container, as far as its summary tells.
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    Keep,
+    Remove,
+    /// Removed once stopped for longer than [STOPPED_GRACE], which takes an inspect.
+    IfStoppedLongAgo,
+}
+
+fn classify(summary: &ContainerSummary, now: SystemTime, max_age: Duration, mode: Reap) -> Verdict {
+    let running = summary.State == "running";
+    let stopped = summary.State == "exited" || summary.State == "dead";
+    let too_old = age(now, summary.Created) > max_age;
+
+    match mode {
+        // Warm containers outlive any call, pool removes them itself
+        _ if running && summary.Labels.contains_key(POOL_LABEL) => Verdict::Keep,
+        Reap::Startup if !running || too_old => Verdict::Remove,
+        Reap::Periodic if too_old => Verdict::Remove,
+        Reap::Periodic if stopped => Verdict::IfStoppedLongAgo,
+        _ => Verdict::Keep,
+    }
+}
+
+async fn stopped_long_ago(
+    api: &DockerApi,
+    summary: &ContainerSummary,
+    now: SystemTime,
+) -> DockerResult<bool> {
+    let state = match api.container(summary.Id.clone()).inspect().await {
+        Ok(inspect) => inspect.State,
+        // Invocation removed it in the meantime
+        Err(DockerError::NotFound(_)) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let stopped_for = humantime::parse_rfc3339_weak(&state.FinishedAt)
+        .ok()
+        .and_then(|finished_at| now.duration_since(finished_at).ok())
+        .unwrap_or(Duration::MAX);
+
+    Ok(stopped_for > STOPPED_GRACE)
+}
+
+fn age(now: SystemTime, created: i64) -> Duration {
+    let created = UNIX_EPOCH + Duration::from_secs(u64::try_from(created).unwrap_or(0));
+    now.duration_since(created).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, Reap, Verdict};
+    use crate::invoke::POOL_LABEL;
+    use simple_faas_docker::v1_37::ContainerSummary;
+    use std::collections::HashMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_classify() {
+        let now = UNIX_EPOCH + Duration::from_secs(10_000);
+        let max_age = Duration::from_secs(3600);
+        let container = |state: &str, created: i64, pooled: bool| ContainerSummary {
+            Id: "id".to_string(),
+            Created: created,
+            State: state.to_string(),
+            Labels: match pooled {
+                true => HashMap::from([(POOL_LABEL.to_string(), "true".to_string())]),
+                false => HashMap::new(),
+            },
+            ..ContainerSummary::default()
+        };
+        let verdict = |summary: &ContainerSummary, mode| classify(summary, now, max_age, mode);
+
+        let fresh_running = container("running", 9_000, false);
+        assert_eq!(verdict(&fresh_running, Reap::Startup), Verdict::Keep);
+        assert_eq!(verdict(&fresh_running, Reap::Periodic), Verdict::Keep);
+
+        let old_running = container("running", 1_000, false);
+        assert_eq!(verdict(&old_running, Reap::Startup), Verdict::Remove);
+        assert_eq!(verdict(&old_running, Reap::Periodic), Verdict::Remove);
+
+        let fresh_exited = container("exited", 9_000, false);
+        assert_eq!(verdict(&fresh_exited, Reap::Startup), Verdict::Remove);
+        assert_eq!(
+            verdict(&fresh_exited, Reap::Periodic),
+            Verdict::IfStoppedLongAgo
+        );
+
+        let fresh_created = container("created", 9_000, false);
+        assert_eq!(verdict(&fresh_created, Reap::Startup), Verdict::Remove);
+        assert_eq!(verdict(&fresh_created, Reap::Periodic), Verdict::Keep);
+
+        let old_warm = container("running", 1_000, true);
+        assert_eq!(verdict(&old_warm, Reap::Startup), Verdict::Keep);
+        assert_eq!(verdict(&old_warm, Reap::Periodic), Verdict::Keep);
+
+        let stopped_warm = container("exited", 9_000, true);
+        assert_eq!(verdict(&stopped_warm, Reap::Startup), Verdict::Remove);
+    }
+}
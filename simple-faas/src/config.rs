@@ -1,18 +1,33 @@
 use anyhow::Context;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use simple_faas_docker::auth::{self, DockerConfig};
+use simple_faas_docker::client::{PoolConfig, TlsConfig};
+use simple_faas_docker::v1_37::{ContainerCreateArgs, HostConfig, Ulimit};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use warp::http::Method;
 
 const DEFAULT_CONFIG_NAME: &str = "config.yml";
 
 pub fn read_default() -> anyhow::Result<Config> {
     let config_reader =
         File::open(DEFAULT_CONFIG_NAME).with_context(|| "Failed to open app config")?;
-    let mut config: Config = serde_yaml::from_reader(config_reader)?;
+    let mut config: serde_yaml::Value = serde_yaml::from_reader(config_reader)?;
+    interpolate_env(&mut config).with_context(|| "Failed to interpolate app config")?;
+    let mut config: Config = serde_yaml::from_value(config)?;
     let docker_config = auth::read_default()?;
     config.docker_config = docker_config;
+    config.apply_defaults();
+    config.load_seccomp_profiles()?;
+    config.check_runtimes_allowed()?;
+    config.check_instance_id()?;
+    config.check_reaper_max_age()?;
+    config.check_pools()?;
+    config.check_http_functions()?;
+    config.check_methods()?;
 
     Ok(config)
 }
@@ -20,19 +35,971 @@ pub fn read_default() -> anyhow::Result<Config> {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub version: u64,
-    pub docker_host: String,
+    /// Docker daemon address, `DOCKER_HOST` env is used when omitted.
+    #[serde(default)]
+    pub docker_host: Option<String>,
+    /// Docker daemon TLS settings, `DOCKER_TLS_VERIFY`/`DOCKER_CERT_PATH` env is used when omitted.
+    #[serde(default)]
+    pub docker_tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub docker_pool: PoolConfig,
+    /// Marks containers of this gateway, so restarted gateway can find the ones it left behind.
+    /// `HOSTNAME` env when omitted, one of them is required.
+    #[serde(default = "default_instance_id")]
+    pub instance_id: String,
+    #[serde(default)]
+    pub reaper: ReaperConfig,
+    /// How many function images are pulled at the same time.
+    #[serde(default = "default_pull_concurrency")]
+    pub pull_concurrency: usize,
     #[serde(default = "default_listen_host")]
     pub listen_host: SocketAddr,
+    /// OCI runtimes functions may request, only daemon default runtime when empty.
+    #[serde(default)]
+    pub allowed_runtimes: Vec<String>,
+    /// Settings every function gets unless it overrides them.
+    #[serde(default)]
+    pub defaults: FunctionDefaults,
     pub functions: HashMap<String, FunctionData>,
     #[serde(default)]
     pub docker_config: DockerConfig,
 }
 
+impl Config {
+    /// Fills settings functions left unset from [Config::defaults].
+    fn apply_defaults(&mut self) {
+        for function in self.functions.values_mut() {
+            function.limits = function.limits.or(&self.defaults.limits);
+            function.security = function.security.or(&self.defaults.security);
+            function.timeout.get_or_insert(self.defaults.timeout);
+            function
+                .max_request_size
+                .get_or_insert(self.defaults.max_request_size);
+            function
+                .max_response_size
+                .get_or_insert(self.defaults.max_response_size);
+        }
+    }
+
+    /// Reaper must not remove containers of functions that are still within their timeout.
+    fn check_reaper_max_age(&self) -> anyhow::Result<()> {
+        for (name, function) in self.functions.iter() {
+            let timeout = function.timeout.unwrap_or(self.defaults.timeout);
+            if timeout + self.defaults.stop_grace_period >= self.reaper.max_age {
+                anyhow::bail!(
+                    "Function {} timeout {:?} plus stop grace period does not fit reaper max_age {:?}",
+                    name,
+                    timeout,
+                    self.reaper.max_age
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Shared fallback id would let gateways on one daemon reap each other's containers.
+    fn check_instance_id(&self) -> anyhow::Result<()> {
+        if self.instance_id.trim().is_empty() {
+            anyhow::bail!("instance_id is required when HOSTNAME env is not set");
+        }
+
+        Ok(())
+    }
+
+    fn check_pools(&self) -> anyhow::Result<()> {
+        for (name, function) in self.functions.iter() {
+            let reuse = function.pool.as_ref().is_some_and(|pool| pool.reuse);
+            let sizes = [function.max_request_size, function.max_response_size];
+            if reuse
+                && sizes
+                    .iter()
+                    .flatten()
+                    .any(|size| size.0 > u64::from(u32::MAX))
+            {
+                anyhow::bail!(
+                    "Function {} reuses containers, its max request and response size must be below 4g",
+                    name
+                );
+            }
+            if let Some(WarmPool {
+                min_warm,
+                max_warm: Some(max_warm),
+                reuse,
+                ..
+            }) = &function.pool
+            {
+                if *reuse && *max_warm == 0 {
+                    anyhow::bail!(
+                        "Function {} reuses containers, its pool max_warm must be at least 1",
+                        name
+                    );
+                }
+                if max_warm < min_warm {
+                    anyhow::bail!(
+                        "Function {} pool max_warm {} is less than min_warm {}",
+                        name,
+                        max_warm,
+                        min_warm
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_http_functions(&self) -> anyhow::Result<()> {
+        for (name, function) in self.functions.iter() {
+            match (function.mode, &function.http) {
+                (FunctionMode::Http, None) => {
+                    anyhow::bail!("Function {} in http mode has no http port", name)
+                }
+                (FunctionMode::Stdin, Some(_)) => {
+                    anyhow::bail!(
+                        "Function {} has http settings but is not in http mode",
+                        name
+                    )
+                }
+                _ => {}
+            }
+            if function.mode == FunctionMode::Http
+                && function.security.network.as_deref() == Some("none")
+            {
+                anyhow::bail!("Function {} in http mode can't have network none", name);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_methods(&self) -> anyhow::Result<()> {
+        for (name, function) in self.functions.iter() {
+            for method in function.methods.iter().flatten() {
+                if Method::from_bytes(method.as_bytes()).is_err() {
+                    anyhow::bail!("Function {} has invalid method {:?}", name, method);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_runtimes_allowed(&self) -> anyhow::Result<()> {
+        for (name, function) in self.functions.iter() {
+            if let Some(runtime) = &function.runtime {
+                if !self.allowed_runtimes.contains(runtime) {
+                    anyhow::bail!(
+                        "Function {} runtime {} is not in allowed_runtimes {:?}",
+                        name,
+                        runtime,
+                        self.allowed_runtimes
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads seccomp profile files, daemon expects profile json itself.
+    fn load_seccomp_profiles(&mut self) -> anyhow::Result<()> {
+        for (name, function) in self.functions.iter_mut() {
+            let security = &mut function.security;
+            security.seccomp_profile = match security.seccomp.as_deref() {
+                None => None,
+                Some(SECCOMP_UNCONFINED) => Some(SECCOMP_UNCONFINED.to_string()),
+                Some(path) => Some(std::fs::read_to_string(path).with_context(|| {
+                    format!("Failed to read seccomp profile {} of {}", path, name)
+                })?),
+            };
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDefaults {
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub security: Security,
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// How long timed out function has to exit after `SIGTERM` before it is killed.
+    #[serde(default = "default_stop_grace_period", with = "humantime_serde")]
+    pub stop_grace_period: Duration,
+    /// Larger request bodies are rejected with `413`.
+    #[serde(default = "default_max_request_size")]
+    pub max_request_size: ByteSize,
+    /// Function writing more output than this is cut off.
+    #[serde(default = "default_max_response_size")]
+    pub max_response_size: ByteSize,
+}
+
+impl Default for FunctionDefaults {
+    fn default() -> Self {
+        FunctionDefaults {
+            limits: Limits::default(),
+            security: Security::default(),
+            timeout: default_timeout(),
+            stop_grace_period: default_stop_grace_period(),
+            max_request_size: default_max_request_size(),
+            max_response_size: default_max_response_size(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FunctionData {
     pub image: String,
+    #[serde(default)]
+    pub pull_policy: PullPolicy,
+    /// Pull image on first call instead of at startup.
+    #[serde(default)]
+    pub lazy_pull: bool,
+    /// Re-pull image this often and switch to it when tag points to a new one.
+    #[serde(default, with = "humantime_serde")]
+    pub refresh_interval: Option<Duration>,
+    #[serde(default)]
+    pub mode: FunctionMode,
+    /// Server of function in [FunctionMode::Http].
+    #[serde(default)]
+    pub http: Option<HttpServer>,
+    /// HTTP methods function accepts, any when omitted.
+    #[serde(default)]
+    pub methods: Option<Vec<String>>,
+    #[serde(default)]
+    pub request_headers: HeaderFilter,
+    #[serde(default)]
+    pub stderr: StderrMode,
+    /// `Content-Type` of function output, unless function sets its own in its response head.
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub response: ResponseMode,
+    /// Send stdout as it is written instead of once function exits. Failure after
+    /// response started aborts its body instead of responding with 502 or 504.
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub security: Security,
+    /// OCI runtime, e.g. `runsc` for gVisor, daemon default when omitted.
+    #[serde(default)]
+    pub runtime: Option<String>,
+    /// Longest run before function is stopped, [FunctionDefaults::timeout] when omitted.
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    /// [FunctionDefaults::max_request_size] when omitted.
+    #[serde(default)]
+    pub max_request_size: Option<ByteSize>,
+    /// [FunctionDefaults::max_response_size] when omitted.
+    #[serde(default)]
+    pub max_response_size: Option<ByteSize>,
+    /// Containers started ahead of calls, every call starts its own container when omitted.
+    #[serde(default)]
+    pub pool: Option<WarmPool>,
+    /// Extra container environment on top of image one.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Replaces image `CMD`.
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+    /// Replaces image `ENTRYPOINT`.
+    #[serde(default)]
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// `user`, `user:group` or numeric ids.
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+impl FunctionData {
+    /// Most request body bytes function accepts.
+    pub fn request_limit(&self) -> u64 {
+        self.max_request_size.map_or(u64::MAX, |size| size.0)
+    }
+
+    /// Most output bytes function may write.
+    pub fn response_limit(&self) -> u64 {
+        self.max_response_size.map_or(u64::MAX, |size| size.0)
+    }
+
+    pub fn accepts(&self, method: &Method) -> bool {
+        match &self.methods {
+            Some(methods) => methods
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(method.as_str())),
+            None => true,
+        }
+    }
+
+    /// Container environment as `NAME=value` pairs.
+    pub fn container_env(&self) -> Vec<String> {
+        let mut env: Vec<String> = self
+            .env
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        env.sort();
+        env
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WarmPool {
+    /// Started containers kept waiting for calls.
+    #[serde(default)]
+    pub min_warm: usize,
+    /// Most idle containers kept after calls with `reuse`, `min_warm` but at least 1 when omitted.
+    #[serde(default)]
+    pub max_warm: Option<usize>,
+    /// Idle containers above `min_warm` are removed after this long.
+    #[serde(default = "default_idle_ttl", with = "humantime_serde")]
+    pub idle_ttl: Duration,
+    /// Container serves calls one after another over length prefixed
+    /// stdin/stdout protocol instead of exiting after one call.
+    #[serde(default)]
+    pub reuse: bool,
+    /// Single call containers get request description on stdin ahead of the body,
+    /// see [Invoker::call_warm](crate::invoke::Invoker::call_warm). Reused ones always do.
+    #[serde(default)]
+    pub request_metadata: bool,
+}
+
+impl WarmPool {
+    pub fn max_warm(&self) -> usize {
+        self.max_warm.unwrap_or(match self.reuse {
+            true => self.min_warm.max(1),
+            false => self.min_warm,
+        })
+    }
+}
+
+fn default_idle_ttl() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+/// Which request headers function gets as `HTTP_*` environment variables.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeaderFilter {
+    /// Only these headers, every header when omitted.
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    /// Never these headers, `Authorization` and `Cookie` when omitted.
+    #[serde(default)]
+    pub deny: Option<Vec<String>>,
+}
+
+const DENIED_HEADERS: [&str; 2] = ["authorization", "cookie"];
+
+impl HeaderFilter {
+    pub fn allows(&self, name: &str) -> bool {
+        let allowed = match &self.allow {
+            Some(allow) => allow.iter().any(|a| a.eq_ignore_ascii_case(name)),
+            None => true,
+        };
+        let denied = match &self.deny {
+            Some(deny) => deny.iter().any(|d| d.eq_ignore_ascii_case(name)),
+            None => DENIED_HEADERS.iter().any(|d| d.eq_ignore_ascii_case(name)),
+        };
+
+        allowed && !denied
+    }
+}
+
+/// How gateway talks to function container.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FunctionMode {
+    /// Request body on stdin, response on stdout, then container exits.
+    #[default]
+    Stdin,
+    /// Container runs HTTP server requests are proxied to.
+    Http,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpServer {
+    /// Port server listens on inside container.
+    pub port: u16,
+    /// Path answering `2xx` once server is ready, accepted connection is enough when omitted.
+    #[serde(default)]
+    pub readiness_path: Option<String>,
+    /// Started container not ready this long is given up on.
+    #[serde(default = "default_ready_timeout", with = "humantime_serde")]
+    pub ready_timeout: Duration,
+}
+
+fn default_ready_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// When function image is pulled from registry.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullPolicy {
+    /// Every time gateway prepares function.
+    Always,
+    /// Only when daemon doesn't have the image yet.
+    #[default]
+    IfNotPresent,
+    /// Image has to be present on daemon already.
+    Never,
+}
+
+/// Where function stderr output goes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StderrMode {
+    /// Gateway log.
+    #[default]
+    Log,
+    /// Base64 encoded `X-Function-Stderr` response header.
+    /// Not a trailer since HTTP/1 server can't send those.
+    Header,
+}
+
+/// How function stdout is turned into response.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    /// Stdout is the response body as is.
+    #[default]
+    Raw,
+    /// Stdout starts with `Status: 201` and `Name: value` header lines followed by an empty line.
+    Cgi,
+    /// Stdout is `{"statusCode": 201, "headers": {..}, "body": "..", "isBase64Encoded": false}`.
+    Json,
+}
+
+/// Resources function container may use, unlimited when omitted.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    #[serde(default)]
+    pub memory: Option<ByteSize>,
+    /// Memory plus swap, same as `memory` disables swap, `-1` or `unlimited` lifts the limit.
+    #[serde(default)]
+    pub memory_swap: Option<SwapLimit>,
+    /// Fraction of CPUs, e.g. `0.5`.
+    #[serde(default)]
+    pub cpus: Option<f64>,
+    /// Relative CPU weight, 1024 by docker default.
+    #[serde(default)]
+    pub cpu_shares: Option<i64>,
+    #[serde(default)]
+    pub pids: Option<i64>,
+    /// Keyed by limit name, e.g. `nofile`.
+    #[serde(default)]
+    pub ulimits: Option<HashMap<String, UlimitValue>>,
+    #[serde(default)]
+    pub shm_size: Option<ByteSize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UlimitValue {
+    pub soft: i64,
+    pub hard: i64,
+}
+
+impl Limits {
+    /// Takes each limit from `self`, or from `defaults` when unset.
+    pub fn or(&self, defaults: &Limits) -> Limits {
+        Limits {
+            memory: self.memory.or(defaults.memory),
+            memory_swap: self.memory_swap.or(defaults.memory_swap),
+            cpus: self.cpus.or(defaults.cpus),
+            cpu_shares: self.cpu_shares.or(defaults.cpu_shares),
+            pids: self.pids.or(defaults.pids),
+            ulimits: self.ulimits.clone().or_else(|| defaults.ulimits.clone()),
+            shm_size: self.shm_size.or(defaults.shm_size),
+        }
+    }
+
+    pub fn host_config(&self) -> HostConfig {
+        let mut ulimits: Vec<Ulimit> = self
+            .ulimits
+            .iter()
+            .flatten()
+            .map(|(name, value)| Ulimit {
+                Name: name.clone(),
+                Soft: value.soft,
+                Hard: value.hard,
+            })
+            .collect();
+        ulimits.sort_by(|a, b| a.Name.cmp(&b.Name));
+
+        HostConfig {
+            Memory: self.memory.map(ByteSize::as_i64),
+            MemorySwap: self.memory_swap.map(SwapLimit::as_i64),
+            NanoCpus: self.cpus.map(|cpus| (cpus * 1e9) as i64),
+            CpuShares: self.cpu_shares,
+            PidsLimit: self.pids,
+            Ulimits: ulimits,
+            ShmSize: self.shm_size.map(ByteSize::as_i64),
+            ..HostConfig::default()
+        }
+    }
+}
+
+/// Seccomp profile disabling syscall filtering.
+const SECCOMP_UNCONFINED: &str = "unconfined";
+/// `nobody:nogroup` on most images.
+const NON_ROOT_USER: &str = "65534:65534";
+const DEFAULT_TMPFS: (&str, &str) = ("/tmp", "rw,noexec,nosuid,size=64m");
+
+/// Sandbox of function container.
+/// Unset options are secure, so a function has to opt out of each one explicitly.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Security {
+    /// `["ALL"]` by default.
+    #[serde(default)]
+    pub cap_drop: Option<Vec<String>>,
+    #[serde(default)]
+    pub cap_add: Option<Vec<String>>,
+    /// `true` by default.
+    #[serde(default)]
+    pub read_only_rootfs: Option<bool>,
+    /// `true` by default.
+    #[serde(default)]
+    pub no_new_privileges: Option<bool>,
+    /// Path to seccomp profile json or `unconfined`, docker default profile when omitted.
+    #[serde(default)]
+    pub seccomp: Option<String>,
+    /// AppArmor profile name, docker default profile when omitted.
+    #[serde(default)]
+    pub apparmor: Option<String>,
+    /// Run as `65534:65534` unless function sets its `user`, `true` by default.
+    #[serde(default)]
+    pub non_root: Option<bool>,
+    /// Mount point to tmpfs options, writable `/tmp` by default.
+    #[serde(default)]
+    pub tmpfs: Option<HashMap<String, String>>,
+    /// Docker network, `none` by default, `bridge` in [FunctionMode::Http].
+    #[serde(default)]
+    pub network: Option<String>,
+    /// Contents of [Security::seccomp] file.
+    #[serde(skip)]
+    pub seccomp_profile: Option<String>,
+}
+
+impl Security {
+    /// Takes each option from `self`, or from `defaults` when unset.
+    pub fn or(&self, defaults: &Security) -> Security {
+        Security {
+            cap_drop: self.cap_drop.clone().or_else(|| defaults.cap_drop.clone()),
+            cap_add: self.cap_add.clone().or_else(|| defaults.cap_add.clone()),
+            read_only_rootfs: self.read_only_rootfs.or(defaults.read_only_rootfs),
+            no_new_privileges: self.no_new_privileges.or(defaults.no_new_privileges),
+            seccomp: self.seccomp.clone().or_else(|| defaults.seccomp.clone()),
+            apparmor: self.apparmor.clone().or_else(|| defaults.apparmor.clone()),
+            non_root: self.non_root.or(defaults.non_root),
+            tmpfs: self.tmpfs.clone().or_else(|| defaults.tmpfs.clone()),
+            network: self.network.clone().or_else(|| defaults.network.clone()),
+            seccomp_profile: None,
+        }
+    }
+
+    /// Sets sandbox options of container, falling back to secure defaults.
+    pub fn apply(&self, args: &mut ContainerCreateArgs) {
+        let host_config = &mut args.HostConfig;
+        host_config.CapDrop = self
+            .cap_drop
+            .clone()
+            .unwrap_or_else(|| vec!["ALL".to_string()]);
+        host_config.CapAdd = self.cap_add.clone().unwrap_or_default();
+        host_config.ReadonlyRootfs = Some(self.read_only_rootfs.unwrap_or(true));
+        host_config.Tmpfs = match &self.tmpfs {
+            Some(tmpfs) => tmpfs.clone(),
+            None => HashMap::from([(DEFAULT_TMPFS.0.to_string(), DEFAULT_TMPFS.1.to_string())]),
+        };
+        host_config.NetworkMode = Some(self.network.clone().unwrap_or_else(|| "none".to_string()));
+
+        host_config.SecurityOpt.clear();
+        if self.no_new_privileges.unwrap_or(true) {
+            host_config
+                .SecurityOpt
+                .push("no-new-privileges".to_string());
+        }
+        if let Some(profile) = &self.seccomp_profile {
+            host_config.SecurityOpt.push(format!("seccomp={}", profile));
+        }
+        if let Some(profile) = &self.apparmor {
+            host_config
+                .SecurityOpt
+                .push(format!("apparmor={}", profile));
+        }
+
+        if args.User.is_none() && self.non_root.unwrap_or(true) {
+            args.User = Some(NON_ROOT_USER.to_string());
+        }
+    }
+}
+
+/// Amount of bytes, written either as a number or with
+/// docker style binary suffix `b`, `k`, `m` or `g`, e.g. `256m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    fn as_i64(self) -> i64 {
+        i64::try_from(self.0).unwrap_or(i64::MAX)
+    }
+}
+
+impl std::str::FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let (number, multiplier) = match s.char_indices().last() {
+            Some((i, 'b')) => (&s[..i], 1),
+            Some((i, 'k')) => (&s[..i], 1 << 10),
+            Some((i, 'm')) => (&s[..i], 1 << 20),
+            Some((i, 'g')) => (&s[..i], 1 << 30),
+            _ => (s.as_str(), 1),
+        };
+        let number: u64 = number
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid byte size {:?}", s))?;
+
+        number
+            .checked_mul(multiplier)
+            .map(ByteSize)
+            .ok_or_else(|| format!("byte size {:?} is too large", s))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ByteSizeVisitor;
+
+        impl<'de> de::Visitor<'de> for ByteSizeVisitor {
+            type Value = ByteSize;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("amount of bytes like 1048576 or 1m")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<ByteSize, E> {
+                Ok(ByteSize(v))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<ByteSize, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(ByteSizeVisitor)
+    }
+}
+
+/// Memory plus swap limit, docker's `-1` stands for unlimited swap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapLimit {
+    Limited(ByteSize),
+    Unlimited,
+}
+
+impl SwapLimit {
+    fn as_i64(self) -> i64 {
+        match self {
+            SwapLimit::Limited(size) => size.as_i64(),
+            SwapLimit::Unlimited => -1,
+        }
+    }
+}
+
+impl Serialize for SwapLimit {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SwapLimit::Limited(size) => size.serialize(serializer),
+            SwapLimit::Unlimited => serializer.serialize_i64(-1),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SwapLimit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SwapLimitVisitor;
+
+        impl<'de> de::Visitor<'de> for SwapLimitVisitor {
+            type Value = SwapLimit;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("amount of bytes like 1048576 or 1m, or -1 or unlimited")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<SwapLimit, E> {
+                match v {
+                    -1 => Ok(SwapLimit::Unlimited),
+                    v => u64::try_from(v)
+                        .map(|v| SwapLimit::Limited(ByteSize(v)))
+                        .map_err(|_| E::custom(format!("invalid swap limit {}", v))),
+                }
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<SwapLimit, E> {
+                Ok(SwapLimit::Limited(ByteSize(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<SwapLimit, E> {
+                match v.trim() {
+                    "-1" | "unlimited" => Ok(SwapLimit::Unlimited),
+                    v => v.parse().map(SwapLimit::Limited).map_err(E::custom),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(SwapLimitVisitor)
+    }
+}
+
+/// Replaces `${VAR}` and `${VAR:-default}` in every string value
+/// with gateway environment, `$$` stands for a literal `$`.
+fn interpolate_env(value: &mut serde_yaml::Value) -> anyhow::Result<()> {
+    match value {
+        serde_yaml::Value::String(s) => {
+            *s = interpolate(s, |name| std::env::var(name).ok())?;
+        }
+        serde_yaml::Value::Sequence(values) => {
+            for value in values {
+                interpolate_env(value)?;
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (_key, value) in mapping.iter_mut() {
+                interpolate_env(value)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn interpolate(s: &str, lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+            continue;
+        }
+
+        let expression = match rest.strip_prefix('{') {
+            Some(after) => after,
+            None => {
+                result.push('$');
+                continue;
+            }
+        };
+        let end = match expression.find('}') {
+            Some(end) => end,
+            None => anyhow::bail!("Unterminated variable in {:?}", s),
+        };
+        let (name, default) = match expression[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&expression[..end], None),
+        };
+
+        // Like shell, `:-` default also replaces empty value
+        let value = match (lookup(name), default) {
+            (Some(value), None) => value,
+            (Some(value), Some(_)) if !value.is_empty() => value,
+            (_, Some(default)) => default.to_string(),
+            (None, None) => anyhow::bail!("Environment variable {} is not set", name),
+        };
+        result.push_str(&value);
+        rest = &expression[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Removal of containers left behind by crashes and failed cleanups.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReaperConfig {
+    #[serde(default = "default_reaper_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    /// Containers older than this are removed even if they are still running.
+    #[serde(default = "default_reaper_max_age", with = "humantime_serde")]
+    pub max_age: Duration,
+}
+
+impl Default for ReaperConfig {
+    fn default() -> Self {
+        ReaperConfig {
+            interval: default_reaper_interval(),
+            max_age: default_reaper_max_age(),
+        }
+    }
+}
+
+fn default_reaper_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_reaper_max_age() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+/// Stable across restarts, so restarted gateway finds containers its crashed run left behind.
+/// Empty when `HOSTNAME` is not set, which [Config::check_instance_id] rejects.
+fn default_instance_id() -> String {
+    std::env::var("HOSTNAME").unwrap_or_default()
+}
+
+fn default_pull_concurrency() -> usize {
+    4
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_stop_grace_period() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_max_request_size() -> ByteSize {
+    ByteSize(1024 * 1024)
+}
+
+fn default_max_response_size() -> ByteSize {
+    ByteSize(16 * 1024 * 1024)
 }
 
 fn default_listen_host() -> SocketAddr {
     SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{interpolate, ByteSize, Limits, Security, WarmPool};
+    use simple_faas_docker::v1_37::ContainerCreateArgs;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_byte_size() {
+        assert_eq!("512".parse(), Ok(ByteSize(512)));
+        assert_eq!("64k".parse(), Ok(ByteSize(64 * 1024)));
+        assert_eq!("256M".parse(), Ok(ByteSize(256 * 1024 * 1024)));
+        assert_eq!("2g".parse(), Ok(ByteSize(2 * 1024 * 1024 * 1024)));
+        assert!("lots".parse::<ByteSize>().is_err());
+        assert!("m".parse::<ByteSize>().is_err());
+
+        let limits: Limits = serde_yaml::from_str("memory: 128m\nshm_size: 65536\n").unwrap();
+        assert_eq!(limits.memory, Some(ByteSize(128 * 1024 * 1024)));
+        assert_eq!(limits.shm_size, Some(ByteSize(65536)));
+    }
+
+    #[test]
+    fn test_limits_defaults() {
+        let defaults: Limits = serde_yaml::from_str("memory: 256m\npids: 64\n").unwrap();
+        let function: Limits = serde_yaml::from_str("memory: 1g\ncpus: 0.5\n").unwrap();
+        let host_config = function.or(&defaults).host_config();
+
+        assert_eq!(host_config.Memory, Some(1024 * 1024 * 1024));
+        assert_eq!(host_config.PidsLimit, Some(64));
+        assert_eq!(host_config.NanoCpus, Some(500_000_000));
+        assert_eq!(host_config.CpuShares, None);
+        assert_eq!(host_config.MemorySwap, None);
+
+        let swap: Limits = serde_yaml::from_str("memory_swap: 512m\n").unwrap();
+        assert_eq!(swap.host_config().MemorySwap, Some(512 * 1024 * 1024));
+        let swap: Limits = serde_yaml::from_str("memory_swap: -1\n").unwrap();
+        assert_eq!(swap.host_config().MemorySwap, Some(-1));
+        let swap: Limits = serde_yaml::from_str("memory_swap: unlimited\n").unwrap();
+        assert_eq!(swap.host_config().MemorySwap, Some(-1));
+        assert!(serde_yaml::from_str::<Limits>("memory_swap: -2\n").is_err());
+    }
+
+    #[test]
+    fn test_pool_max_warm() {
+        let pool: WarmPool = serde_yaml::from_str("min_warm: 2\n").unwrap();
+        assert_eq!(pool.max_warm(), 2);
+        let pool: WarmPool = serde_yaml::from_str("min_warm: 0\n").unwrap();
+        assert_eq!(pool.max_warm(), 0);
+        let pool: WarmPool = serde_yaml::from_str("min_warm: 0\nreuse: true\n").unwrap();
+        assert_eq!(pool.max_warm(), 1);
+        let pool: WarmPool = serde_yaml::from_str("min_warm: 1\nmax_warm: 3\n").unwrap();
+        assert_eq!(pool.max_warm(), 3);
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let lookup = |name: &str| match name {
+            "TOKEN" => Some("secret".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+
+        assert_eq!(interpolate("plain", lookup).unwrap(), "plain");
+        assert_eq!(
+            interpolate("Bearer ${TOKEN}!", lookup).unwrap(),
+            "Bearer secret!"
+        );
+        assert_eq!(
+            interpolate("${MISSING:-fallback}", lookup).unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            interpolate("${EMPTY:-fallback}", lookup).unwrap(),
+            "fallback"
+        );
+        assert_eq!(interpolate("${EMPTY}", lookup).unwrap(), "");
+        assert_eq!(interpolate("$$TOKEN $5", lookup).unwrap(), "$TOKEN $5");
+        assert!(interpolate("${MISSING}", lookup).is_err());
+        assert!(interpolate("${TOKEN", lookup).is_err());
+    }
+
+    #[test]
+    fn test_security_defaults() {
+        let mut args = ContainerCreateArgs {
+            Image: "alpine:latest".to_string(),
+            Cmd: None,
+            Entrypoint: None,
+            Env: vec![],
+            WorkingDir: None,
+            User: None,
+            Labels: HashMap::new(),
+            AttachStdin: false,
+            OpenStdin: false,
+            StdinOnce: false,
+            Tty: false,
+            HostConfig: Limits::default().host_config(),
+        };
+        Security::default().apply(&mut args);
+
+        assert_eq!(args.User.as_deref(), Some("65534:65534"));
+        assert_eq!(args.HostConfig.CapDrop, vec!["ALL".to_string()]);
+        assert_eq!(args.HostConfig.ReadonlyRootfs, Some(true));
+        assert_eq!(args.HostConfig.NetworkMode.as_deref(), Some("none"));
+        assert_eq!(args.HostConfig.SecurityOpt, vec!["no-new-privileges"]);
+        assert!(args.HostConfig.Tmpfs.contains_key("/tmp"));
+
+        let defaults: Security = serde_yaml::from_str("network: bridge\n").unwrap();
+        let function: Security =
+            serde_yaml::from_str("read_only_rootfs: false\nnon_root: false\ncap_drop: []\n")
+                .unwrap();
+        args.User = None;
+        function.or(&defaults).apply(&mut args);
+
+        assert_eq!(args.User, None);
+        assert!(args.HostConfig.CapDrop.is_empty());
+        assert_eq!(args.HostConfig.ReadonlyRootfs, Some(false));
+        assert_eq!(args.HostConfig.NetworkMode.as_deref(), Some("bridge"));
+    }
+}
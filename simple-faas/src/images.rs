@@ -0,0 +1,260 @@
+use crate::config::{Config, FunctionData, PullPolicy};
+use anyhow::{bail, Context};
+use futures_util::StreamExt;
+use log::{debug, info, trace, warn};
+use simple_faas_docker::error::DockerError;
+use simple_faas_docker::v1_37::{Api as DockerApi, Image, ProgressDetail};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+
+/// Image function containers are created from.
+#[derive(Debug, Clone, PartialEq)]
+enum ImageState {
+    /// Not pulled yet, first call pulls it.
+    Pending,
+    /// Id of image to create containers from. Pinned by id,
+    /// so calls keep using the same image until refresh deploys another one.
+    Ready(String),
+    /// Image could not be pulled, function calls fail with this reason.
+    Unavailable(String),
+}
+
+struct FunctionImage {
+    state: RwLock<ImageState>,
+    /// Held while image is being pulled, so concurrent calls wait for one pull.
+    pull: Mutex<()>,
+}
+
+/// Images of every configured function. Cheap to clone.
+#[derive(Clone)]
+pub struct FunctionImages {
+    config: Arc<Config>,
+    api: DockerApi,
+    images: Arc<HashMap<String, FunctionImage>>,
+}
+
+impl FunctionImages {
+    pub fn new(config: Arc<Config>, api: DockerApi) -> Self {
+        let images = config
+            .functions
+            .keys()
+            .map(|name| {
+                let image = FunctionImage {
+                    state: RwLock::new(ImageState::Pending),
+                    pull: Mutex::new(()),
+                };
+                (name.clone(), image)
+            })
+            .collect();
+
+        FunctionImages {
+            config,
+            api,
+            images: Arc::new(images),
+        }
+    }
+
+    /// Pulls images of functions that are not lazy, at most `concurrency` at a time.
+    pub fn spawn_pulls(&self, concurrency: usize) {
+        let images = self.clone();
+        tokio::spawn(async move {
+            let names: Vec<&String> = images
+                .config
+                .functions
+                .iter()
+                .filter(|(_name, function)| !function.lazy_pull)
+                .map(|(name, _function)| name)
+                .collect();
+            info!("Pulling {} function image(s)", names.len());
+
+            futures_util::stream::iter(names)
+                .for_each_concurrent(concurrency.max(1), |name| async {
+                    // Outcome is logged and kept in function state
+                    let _ = images.image(name).await;
+                })
+                .await;
+            info!("Finished pulling function images");
+        });
+    }
+
+    /// Image to create function container from, pulled on first use.
+    pub async fn image(&self, name: &str) -> Result<String, String> {
+        let function_image = match self.images.get(name) {
+            Some(function_image) => function_image,
+            None => return Err(format!("Function {} is not configured", name)),
+        };
+
+        if let Some(result) = ready(&function_image.state) {
+            return result;
+        }
+
+        let _pull = function_image.pull.lock().await;
+        // Someone else could have pulled it while we waited
+        if let Some(result) = ready(&function_image.state) {
+            return result;
+        }
+
+        let function = &self.config.functions[name];
+        let state = match prepare_image(&self.api, name, function, function.pull_policy).await {
+            Ok(image) => {
+                info!("Function {} is available with image {}", name, image);
+                ImageState::Ready(image)
+            }
+            Err(e) => {
+                let reason = format!("{:#}", e);
+                warn!("Function {} is unavailable: {}", name, reason);
+                ImageState::Unavailable(reason)
+            }
+        };
+        *function_image
+            .state
+            .write()
+            .expect("Image state lock poisoned") = state;
+
+        ready(&function_image.state).expect("Image state was just set")
+    }
+
+    /// Periodically re-pulls images of functions with `refresh_interval`.
+    pub fn spawn_refreshes(&self) {
+        for (name, function) in self.config.functions.iter() {
+            let interval = match function.refresh_interval {
+                Some(interval) => interval,
+                None => continue,
+            };
+
+            let images = self.clone();
+            let name = name.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(interval);
+                // First tick completes immediately, initial pull takes care of it
+                interval.tick().await;
+
+                loop {
+                    interval.tick().await;
+                    images.refresh(&name).await;
+                }
+            });
+        }
+    }
+
+    /// Re-pulls function image and switches new calls to it if tag moved.
+    /// Calls in flight finish with the image they started with.
+    async fn refresh(&self, name: &str) {
+        let function_image = &self.images[name];
+        let function = &self.config.functions[name];
+        let pull_policy = match function.pull_policy {
+            // Image can still be replaced on daemon by other means
+            PullPolicy::Never => PullPolicy::Never,
+            PullPolicy::Always | PullPolicy::IfNotPresent => PullPolicy::Always,
+        };
+
+        let _pull = function_image.pull.lock().await;
+        let image = match prepare_image(&self.api, name, function, pull_policy).await {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("Failed to refresh function {}: {:#}", name, e);
+                return;
+            }
+        };
+
+        let mut state = function_image
+            .state
+            .write()
+            .expect("Image state lock poisoned");
+        match &*state {
+            ImageState::Ready(current) if *current == image => {
+                debug!("Function {} image {} is up to date", name, function.image);
+            }
+            ImageState::Ready(current) => {
+                info!(
+                    "Deployed function {} image {}: {} -> {}",
+                    name, function.image, current, image
+                );
+                *state = ImageState::Ready(image);
+            }
+            ImageState::Pending | ImageState::Unavailable(_) => {
+                info!(
+                    "Deployed function {} image {}: {}",
+                    name, function.image, image
+                );
+                *state = ImageState::Ready(image);
+            }
+        }
+    }
+}
+
+fn ready(state: &RwLock<ImageState>) -> Option<Result<String, String>> {
+    match &*state.read().expect("Image state lock poisoned") {
+        ImageState::Pending => None,
+        ImageState::Ready(image) => Some(Ok(image.clone())),
+        ImageState::Unavailable(reason) => Some(Err(reason.clone())),
+    }
+}
+
+/// Makes sure function image is present according to pull policy and returns its id.
+async fn prepare_image(
+    api: &DockerApi,
+    name: &str,
+    function: &FunctionData,
+    pull_policy: PullPolicy,
+) -> anyhow::Result<String> {
+    let image = Image::parse(&function.image)?;
+    let reference = image.reference();
+
+    let present = match api.images().inspect(&reference).await {
+        Ok(_) => true,
+        Err(DockerError::NotFound(_)) => false,
+        Err(e) => return Err(e.into()),
+    };
+
+    match (pull_policy, present) {
+        (PullPolicy::Always, _) | (PullPolicy::IfNotPresent, false) => {
+            pull(api, name, function).await?
+        }
+        (PullPolicy::IfNotPresent, true) => debug!("Function {} image is present", name),
+        (PullPolicy::Never, true) => {}
+        (PullPolicy::Never, false) => {
+            bail!(
+                "Image {} is not present and pull_policy is never",
+                reference
+            )
+        }
+    }
+
+    let pulled = api.images().inspect(&reference).await?;
+    if let Some(digest) = &image.digest {
+        if !pulled.has_digest(digest) {
+            bail!(
+                "Image {} does not match pinned digest, pulled {:?}",
+                function.image,
+                pulled.RepoDigests
+            );
+        }
+        debug!("Function {} image digest {} verified", name, digest);
+    }
+
+    Ok(pulled.Id)
+}
+
+async fn pull(api: &DockerApi, name: &str, function: &FunctionData) -> anyhow::Result<()> {
+    debug!("Pulling function {} from {}", name, function.image);
+
+    let mut progress = api
+        .images()
+        .pull_with_progress(function.image.clone())
+        .await
+        .with_context(|| format!("Failed to pull {}", function.image))?;
+    while let Some(event) = progress.next().await {
+        let event = event?;
+        match event.progressDetail {
+            // Byte level download/extract progress is too chatty for debug
+            Some(ProgressDetail {
+                current: Some(_), ..
+            }) => trace!("{} pull: {}", name, event),
+            _ => debug!("{} pull: {}", name, event),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,776 @@
+use crate::cleanup::{Cleanup, ContainerGuard, FUNCTION_LABEL, INSTANCE_LABEL, INVOCATION_LABEL};
+use crate::config::{Config, FunctionData, FunctionMode};
+use crate::proxy::{self, HttpClient};
+use crate::request::{BodyError, FunctionRequest, RequestBody};
+use crate::response::FunctionOutput;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::stream::{self, StreamExt};
+use hyper::{Body, Response};
+use log::{debug, info, warn};
+use simple_faas_docker::demux::{FrameStream, StdStream};
+use simple_faas_docker::error::{DockerError, Result as DockerResult};
+use simple_faas_docker::v1_37::{
+    Api as DockerApi, AttachStdin, Container, ContainerAttach, ContainerAttachArgs,
+    ContainerCreateArgs, ContainerLogs,
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// How many times container creation is attempted while docker daemon is unreachable.
+const CREATE_ATTEMPTS: u32 = 3;
+/// Label of containers kept in warm pool, reaper leaves running ones alone.
+pub const POOL_LABEL: &str = "simple-faas.pool";
+/// Length prefix of messages in reuse protocol.
+const LENGTH_PREFIX: usize = 4;
+/// Network of [FunctionMode::Http] containers unless function sets its own.
+const HTTP_NETWORK: &str = "bridge";
+/// Output events waiting for a slow caller before function is paused.
+const RUN_EVENTS_BUFFER: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum InvokeError {
+    #[error(transparent)]
+    Docker(#[from] DockerError),
+    #[error("Function server is not ready: {0}")]
+    NotReady(String),
+    #[error("Function server failed to respond: {0}")]
+    Upstream(#[from] hyper::Error),
+    #[error("Function server did not respond in {0:?}")]
+    Timeout(Duration),
+    #[error("Invalid function request: {0}")]
+    BadRequest(String),
+    #[error(transparent)]
+    Body(#[from] BodyError),
+    #[error("Function server response exceeds {0} bytes")]
+    ResponseTooLarge(u64),
+}
+
+pub type InvokeResult<T> = Result<T, InvokeError>;
+
+/// Progress of function run started by [Invoker::spawn_run].
+#[derive(Debug)]
+pub enum RunEvent {
+    Stdout(Bytes),
+    Stderr(Bytes),
+    /// Last event. Output sent in other events is not repeated in its logs.
+    Exited(FunctionOutput),
+}
+
+/// Receiving end of function run, dropping it stops the function.
+pub type RunEvents = mpsc::Receiver<InvokeResult<RunEvent>>;
+
+/// Why container output stopped being read.
+enum OutputEnd {
+    Closed,
+    TooLarge,
+    Abandoned,
+}
+
+/// What reused container answered to a request.
+enum Reply {
+    Response(Bytes),
+    Closed,
+    TooLarge,
+}
+
+/// Started function container waiting for input on stdin.
+pub struct FunctionContainer {
+    container: ContainerGuard,
+    /// Image id container was created from.
+    image: String,
+    stdin: AttachStdin,
+    output: FrameStream,
+    /// Stdout received past the last response of reused container.
+    stdout: BytesMut,
+    /// Ready server of [FunctionMode::Http] container.
+    address: Option<SocketAddr>,
+}
+
+impl FunctionContainer {
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    /// Address of ready server in [FunctionMode::Http].
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.address
+    }
+}
+
+/// Runs function containers. Cheap to clone.
+#[derive(Clone)]
+pub struct Invoker {
+    config: Arc<Config>,
+    api: DockerApi,
+    cleanup: Cleanup,
+    http: HttpClient,
+}
+
+impl Invoker {
+    pub fn new(config: Arc<Config>, api: DockerApi, cleanup: Cleanup) -> Self {
+        Invoker {
+            config,
+            api,
+            cleanup,
+            http: proxy::http_client(),
+        }
+    }
+
+    /// Creates, attaches to and starts function container,
+    /// then waits for its server in [FunctionMode::Http].
+    /// `pooled` containers are marked so reaper doesn't remove them for their age.
+    /// `request_env` goes in front of function environment, which wins on conflict.
+    pub async fn start(
+        &self,
+        name: &str,
+        function: &FunctionData,
+        image: String,
+        pooled: bool,
+        request_env: Vec<String>,
+    ) -> InvokeResult<FunctionContainer> {
+        let invocation = format!("{:016x}", rand::random::<u64>());
+        let mut labels = HashMap::from([
+            (FUNCTION_LABEL.to_string(), name.to_string()),
+            (INVOCATION_LABEL.to_string(), invocation.clone()),
+            (INSTANCE_LABEL.to_string(), self.config.instance_id.clone()),
+        ]);
+        if pooled {
+            labels.insert(POOL_LABEL.to_string(), "warm".to_string());
+        }
+
+        let mut env: Vec<String> = request_env
+            .into_iter()
+            .filter(|variable| {
+                let (variable_name, _value) = variable.split_once('=').unwrap_or((variable, ""));
+                !function.env.contains_key(variable_name)
+            })
+            .collect();
+        env.extend(function.container_env());
+
+        let mut container_create_opts = ContainerCreateArgs {
+            Image: image.clone(),
+            Cmd: function.command.clone(),
+            Entrypoint: function.entrypoint.clone(),
+            Env: env,
+            WorkingDir: function.working_dir.clone(),
+            User: function.user.clone(),
+            Labels: labels,
+            AttachStdin: true,
+            OpenStdin: true,
+            StdinOnce: true,
+            Tty: false,
+            HostConfig: function.limits.host_config(),
+        };
+        function.security.apply(&mut container_create_opts);
+        container_create_opts.HostConfig.Runtime = function.runtime.clone();
+        if function.mode == FunctionMode::Http && function.security.network.is_none() {
+            container_create_opts.HostConfig.NetworkMode = Some(HTTP_NETWORK.to_string());
+        }
+        let network = container_create_opts.HostConfig.NetworkMode.clone();
+        let container = create_container(&self.api, container_create_opts).await?;
+        debug!(
+            "Function {} invocation {} runs in container {}",
+            name,
+            invocation,
+            container.id()
+        );
+        // Removed on any return below, including errors
+        let container = self.cleanup.guard(container);
+        // Output of http server is left to container logs, nobody would read it here
+        let attach_output = function.mode == FunctionMode::Stdin;
+        let attach_opts = ContainerAttachArgs {
+            stream: true,
+            stdin: true,
+            stdout: attach_output,
+            stderr: attach_output,
+            logs: false,
+        };
+        let ContainerAttach { stdin, output } = container.attach(attach_opts).await?;
+        container.start().await?;
+
+        let address = match (function.mode, &function.http) {
+            (FunctionMode::Http, Some(server)) => {
+                let inspect = container.inspect().await?;
+                let ip: IpAddr = inspect
+                    .ip_address(network.as_deref())
+                    .and_then(|ip| ip.parse().ok())
+                    .ok_or_else(|| {
+                        InvokeError::NotReady(format!(
+                            "container has no address on network {}",
+                            network.as_deref().unwrap_or("default")
+                        ))
+                    })?;
+                let address = SocketAddr::new(ip, server.port);
+                proxy::wait_ready(&self.http, address, server)
+                    .await
+                    .map_err(InvokeError::NotReady)?;
+                debug!("Function {} server is ready on {}", name, address);
+                Some(address)
+            }
+            _ => None,
+        };
+
+        Ok(FunctionContainer {
+            container,
+            image,
+            stdin,
+            output,
+            stdout: BytesMut::new(),
+            address,
+        })
+    }
+
+    /// Single call in a fresh container, which gets request described in its environment.
+    pub async fn call(
+        &self,
+        name: &str,
+        function: &FunctionData,
+        image: String,
+        request: FunctionRequest,
+    ) -> InvokeResult<RunEvents> {
+        let request_env = request.cgi_env(name, &function.request_headers);
+        let container = self
+            .start(name, function, image, false, request_env)
+            .await?;
+        Ok(self.spawn_run(name, container, request.body))
+    }
+
+    /// Single call in a warm container started ahead of it. Request description
+    /// can't be in its environment, with `request_metadata` pool setting it is
+    /// written to stdin ahead of the body, otherwise stdin is just the body.
+    pub fn call_warm(
+        &self,
+        name: &str,
+        function: &FunctionData,
+        container: FunctionContainer,
+        request: FunctionRequest,
+    ) -> RunEvents {
+        let with_metadata = function
+            .pool
+            .as_ref()
+            .is_some_and(|pool| pool.request_metadata);
+        if !with_metadata {
+            return self.spawn_run(name, container, request.body);
+        }
+
+        let metadata = request_metadata(&request.cgi_env(name, &function.request_headers));
+        let input = stream::once(async move { Ok(metadata) })
+            .chain(request.body)
+            .boxed();
+        self.spawn_run(name, container, input)
+    }
+
+    /// Streams input to started container and reports its output in background as it comes.
+    pub fn spawn_run(
+        &self,
+        name: &str,
+        container: FunctionContainer,
+        input: RequestBody,
+    ) -> RunEvents {
+        let (sender, receiver) = mpsc::channel(RUN_EVENTS_BUFFER);
+        tokio::spawn(self.clone().run(name.to_string(), container, input, sender));
+
+        receiver
+    }
+
+    async fn run(
+        self,
+        name: String,
+        container: FunctionContainer,
+        input: RequestBody,
+        events: mpsc::Sender<InvokeResult<RunEvent>>,
+    ) {
+        let function = &self.config.functions[&name];
+        let limit = function.response_limit();
+        let FunctionContainer {
+            container,
+            mut stdin,
+            mut output,
+            ..
+        } = container;
+
+        // Output is read while input is written, otherwise function
+        // blocked on full stdout pipe would never consume its stdin.
+        let write_input = async move {
+            let mut input = input;
+            while let Some(chunk) = input.next().await {
+                if let Err(e) = stdin.write_all(&chunk?).await {
+                    // Function is free to exit without reading its input
+                    debug!("Failed to write function input: {}", e);
+                    return Ok(());
+                }
+            }
+            if let Err(e) = stdin.shutdown().await {
+                debug!("Failed to close function input: {}", e);
+            }
+
+            Ok::<_, InvokeError>(())
+        };
+        let read_output = async {
+            let mut received = 0;
+            while let Some(frame) = output.next().await {
+                let frame = frame?;
+                received += frame.data.len() as u64;
+                if received > limit {
+                    return Ok(OutputEnd::TooLarge);
+                }
+
+                let event = match frame.stream {
+                    StdStream::Stdout => RunEvent::Stdout(frame.data),
+                    StdStream::Stderr => RunEvent::Stderr(frame.data),
+                    StdStream::Stdin => continue,
+                };
+                if events.send(Ok(event)).await.is_err() {
+                    return Ok(OutputEnd::Abandoned);
+                }
+            }
+
+            Ok::<_, InvokeError>(OutputEnd::Closed)
+        };
+        let run = async {
+            let ((), end) = tokio::try_join!(write_input, read_output)?;
+            let output = match end {
+                OutputEnd::Closed => {
+                    let exit = container.wait().await?;
+                    let error = exit.Error.map(|e| e.Message);
+                    exited(&container, ContainerLogs::default(), exit.StatusCode, error).await?
+                }
+                OutputEnd::TooLarge => self.cut_off(&container, limit).await,
+                OutputEnd::Abandoned => return Ok(None),
+            };
+
+            Ok::<_, InvokeError>(Some(output))
+        };
+
+        let timeout = function.timeout.unwrap_or(Duration::MAX);
+        let result = match tokio::time::timeout(timeout, run).await {
+            Ok(result) => result,
+            Err(_) => self
+                .stop_timed_out(&container, timeout)
+                .await
+                .map(Some)
+                .map_err(InvokeError::from),
+        };
+
+        let event = match result {
+            Ok(Some(output)) => Ok(RunEvent::Exited(output)),
+            Ok(None) => {
+                debug!(
+                    "Caller of {} went away, removing container {}",
+                    name,
+                    container.id()
+                );
+                return;
+            }
+            Err(e) => Err(e),
+        };
+        // Container is removed either way once it is dropped
+        let _ = events.send(event).await;
+    }
+
+    /// Forwards call to server of [FunctionMode::Http] container at `address`.
+    ///
+    /// Response body is streamed from container, so `keep` holding it is kept until
+    /// body is sent. Function timeout covers the whole exchange, once it passes
+    /// `discard` gives up the container of `keep` and the container is stopped.
+    /// Container of server failing to respond is given up and removed the same way.
+    pub async fn proxy<T, D>(
+        &self,
+        function: &FunctionData,
+        address: SocketAddr,
+        request: FunctionRequest,
+        keep: T,
+        discard: D,
+    ) -> InvokeResult<Response<Body>>
+    where
+        T: Send + 'static,
+        D: FnOnce(T) -> FunctionContainer + Copy + Send + 'static,
+    {
+        let upstream = proxy::upstream_request(address, request)
+            .map_err(|e| InvokeError::BadRequest(e.to_string()))?;
+
+        let timeout = function.timeout.unwrap_or(Duration::MAX);
+        let deadline = function.timeout.map(|timeout| Instant::now() + timeout);
+        let invoker = self.clone();
+        let on_timeout = move |keep: T| {
+            let container = discard(keep);
+            tokio::spawn(async move {
+                if let Err(e) = invoker.stop_timed_out(&container.container, timeout).await {
+                    warn!(
+                        "Failed to stop container {}: {}",
+                        container.container.id(),
+                        e
+                    );
+                }
+            });
+        };
+
+        let response = self.http.request(upstream);
+        let response = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, response).await {
+                Ok(response) => response,
+                Err(_) => {
+                    on_timeout(keep);
+                    return Err(InvokeError::Timeout(timeout));
+                }
+            },
+            None => response.await,
+        };
+        let response = match response {
+            Ok(response) => proxy::downstream_response(response),
+            Err(e) => {
+                // Server that refused or dropped the call must not serve the next one
+                drop(discard(keep));
+                return Err(e.into());
+            }
+        };
+
+        let limit = function.response_limit();
+        let response =
+            proxy::limit_response(response, limit).ok_or(InvokeError::ResponseTooLarge(limit))?;
+        Ok(proxy::deadline_response(
+            response, deadline, keep, on_timeout,
+        ))
+    }
+
+    /// Sends one request to container speaking reuse protocol.
+    ///
+    /// Both request and response are a 4 byte big endian length followed by that many bytes,
+    /// request on stdin and response on stdout. Request is preceded by [request_metadata]
+    /// block made of `request_env`. Container is handed back unless it exited or timed out.
+    pub async fn run_reused(
+        &self,
+        function: &FunctionData,
+        mut container: FunctionContainer,
+        request_env: &[String],
+        input: Bytes,
+    ) -> DockerResult<(FunctionOutput, Option<FunctionContainer>)> {
+        let limit = function.response_limit();
+        let mut message = BytesMut::from(&request_metadata(request_env)[..]);
+        let length = u32::try_from(input.len()).expect("Request body is limited below 4 GiB");
+        message.extend_from_slice(&length.to_be_bytes());
+        message.extend_from_slice(&input);
+
+        let FunctionContainer {
+            stdin,
+            output,
+            stdout,
+            ..
+        } = &mut container;
+        let run = exchange(stdin, output, stdout, &message, limit);
+
+        let timeout = function.timeout.unwrap_or(Duration::MAX);
+        let (response, stderr, request_sent) = match tokio::time::timeout(timeout, run).await {
+            Ok(result) => result?,
+            Err(_) => {
+                let output = self.stop_timed_out(&container.container, timeout).await?;
+                return Ok((output, None));
+            }
+        };
+
+        match response {
+            Reply::Response(stdout) => {
+                let output = FunctionOutput {
+                    logs: ContainerLogs { stdout, stderr },
+                    ..FunctionOutput::default()
+                };
+                // Rest of the request would be taken for the next one
+                match request_sent {
+                    true => Ok((output, Some(container))),
+                    false => {
+                        debug!(
+                            "Reused container {} answered before reading whole request",
+                            container.container.id()
+                        );
+                        Ok((output, None))
+                    }
+                }
+            }
+            Reply::TooLarge => {
+                let output = self.cut_off(&container.container, limit).await;
+                Ok((output, None))
+            }
+            Reply::Closed => {
+                let exit = container.container.wait().await?;
+                info!(
+                    "Reused container {} exited with code {}",
+                    container.container.id(),
+                    exit.StatusCode
+                );
+                let logs = ContainerLogs {
+                    stdout: container.stdout.split().freeze(),
+                    stderr,
+                };
+                let error = exit
+                    .Error
+                    .map(|e| e.Message)
+                    .filter(|m| !m.is_empty())
+                    .unwrap_or_else(|| "exited without response".to_string());
+                let output =
+                    exited(&container.container, logs, exit.StatusCode, Some(error)).await?;
+                Ok((output, None))
+            }
+        }
+    }
+
+    async fn stop_timed_out(
+        &self,
+        container: &Container,
+        timeout: Duration,
+    ) -> DockerResult<FunctionOutput> {
+        debug!("Stopping timed out container {}", container.id());
+        container
+            .stop(self.config.defaults.stop_grace_period)
+            .await?;
+
+        Ok(FunctionOutput {
+            timed_out: Some(timeout),
+            ..FunctionOutput::default()
+        })
+    }
+
+    /// Kills container that wrote more than `limit` output bytes.
+    async fn cut_off(&self, container: &Container, limit: u64) -> FunctionOutput {
+        debug!("Killing container {} over output limit", container.id());
+        // Removal kills it anyway, this only stops output sooner
+        if let Err(e) = container.kill("KILL").await {
+            debug!("Failed to kill container {}: {}", container.id(), e);
+        }
+
+        FunctionOutput {
+            output_limit: Some(limit),
+            ..FunctionOutput::default()
+        }
+    }
+}
+
+/// Output of container that exited by itself.
+async fn exited(
+    container: &Container,
+    logs: ContainerLogs,
+    exit_code: i64,
+    error: Option<String>,
+) -> DockerResult<FunctionOutput> {
+    let oom_killed = match exit_code {
+        0 => false,
+        _ => container.inspect().await?.State.OOMKilled,
+    };
+
+    Ok(FunctionOutput {
+        logs,
+        exit_code,
+        oom_killed,
+        error: error.filter(|m| !m.is_empty()),
+        ..FunctionOutput::default()
+    })
+}
+
+/// Writes reuse protocol `message` to `stdin` and reads until length prefixed response
+/// is on `stdout`. Returns reply, stderr and whether the whole message was written.
+///
+/// Output is read while message is written, otherwise function blocked
+/// on full stdout pipe would never consume the rest of its stdin.
+async fn exchange<W: AsyncWrite + Unpin>(
+    stdin: &mut W,
+    output: &mut FrameStream,
+    stdout: &mut BytesMut,
+    message: &[u8],
+    limit: u64,
+) -> DockerResult<(Reply, Bytes, bool)> {
+    let write_message = async {
+        stdin.write_all(message).await?;
+        stdin.flush().await
+    };
+    let read_reply = async {
+        let mut stderr = BytesMut::new();
+        loop {
+            if response_length(stdout).is_some_and(|length| length > limit)
+                || stderr.len() as u64 > limit
+            {
+                return Ok::<_, DockerError>((Reply::TooLarge, stderr.freeze()));
+            }
+            if let Some(response) = take_response(stdout) {
+                return Ok((Reply::Response(response), stderr.freeze()));
+            }
+
+            match output.next().await {
+                Some(frame) => {
+                    let frame = frame?;
+                    match frame.stream {
+                        StdStream::Stdout => stdout.extend_from_slice(&frame.data),
+                        StdStream::Stderr => stderr.extend_from_slice(&frame.data),
+                        StdStream::Stdin => {}
+                    }
+                }
+                None => return Ok((Reply::Closed, stderr.freeze())),
+            }
+        }
+    };
+    tokio::pin!(write_message, read_reply);
+
+    let mut written = false;
+    loop {
+        tokio::select! {
+            result = &mut write_message, if !written => {
+                written = true;
+                // Container that died can't read input, its exit is reported by reply
+                if let Err(e) = result {
+                    debug!("Failed to write function input: {}", e);
+                }
+            }
+            reply = &mut read_reply => {
+                let (reply, stderr) = reply?;
+                return Ok((reply, stderr, written));
+            }
+        }
+    }
+}
+
+/// Request description for warm containers: `NAME=value` lines of `request_env`,
+/// prefixed with their 4 byte big endian length like any reuse protocol message.
+fn request_metadata(request_env: &[String]) -> Bytes {
+    let lines: String = request_env
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let length = u32::try_from(lines.len()).expect("Request headers are limited below 4 GiB");
+
+    let mut metadata = BytesMut::with_capacity(LENGTH_PREFIX + lines.len());
+    metadata.extend_from_slice(&length.to_be_bytes());
+    metadata.extend_from_slice(lines.as_bytes());
+    metadata.freeze()
+}
+
+/// Length of response at the start of stdout, once its prefix is received.
+fn response_length(stdout: &[u8]) -> Option<u64> {
+    let prefix: [u8; LENGTH_PREFIX] = stdout.get(..LENGTH_PREFIX)?.try_into().ok()?;
+    Some(u64::from(u32::from_be_bytes(prefix)))
+}
+
+/// Takes length prefixed response off stdout once it is fully received.
+fn take_response(stdout: &mut BytesMut) -> Option<Bytes> {
+    let length = response_length(stdout)? as usize;
+    if stdout.len() < LENGTH_PREFIX + length {
+        return None;
+    }
+
+    stdout.advance(LENGTH_PREFIX);
+    Some(stdout.split_to(length).freeze())
+}
+
+async fn create_container(api: &DockerApi, opts: ContainerCreateArgs) -> DockerResult<Container> {
+    let mut attempt = 1;
+    loop {
+        match api.containers().create(opts.clone()).await {
+            Err(e) if e.is_retryable() && attempt < CREATE_ATTEMPTS => {
+                warn!(
+                    "Retrying container create ({}/{}): {}",
+                    attempt, CREATE_ATTEMPTS, e
+                );
+                tokio::time::sleep(Duration::from_millis(100 * u64::from(attempt))).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exchange, request_metadata, take_response, Reply};
+    use bytes::{Bytes, BytesMut};
+    use futures_util::stream::{self, StreamExt};
+    use simple_faas_docker::demux::{Frame, FrameStream, StdStream};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, DuplexStream};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_take_response() {
+        let mut stdout = BytesMut::from(&[0u8, 0, 0, 2, b'o', b'k', 0, 0][..]);
+        assert_eq!(take_response(&mut stdout), Some(Bytes::from_static(b"ok")));
+        assert_eq!(take_response(&mut stdout), None);
+
+        stdout.extend_from_slice(&[0, 0]);
+        assert_eq!(take_response(&mut stdout), Some(Bytes::new()));
+        assert!(stdout.is_empty());
+    }
+
+    #[test]
+    fn test_request_metadata() {
+        let env = vec![
+            "REQUEST_METHOD=GET".to_string(),
+            "QUERY_STRING=a=1".to_string(),
+        ];
+        assert_eq!(
+            request_metadata(&env),
+            Bytes::from_static(b"\0\0\0\x24REQUEST_METHOD=GET\nQUERY_STRING=a=1\n")
+        );
+        assert_eq!(request_metadata(&[]), Bytes::from_static(&[0, 0, 0, 0]));
+    }
+
+    /// Reused function echoing request back as it reads it.
+    async fn echo(mut stdin: DuplexStream, stdout: mpsc::Sender<Frame>) -> std::io::Result<()> {
+        let mut prefix = [0; 4];
+        stdin.read_exact(&mut prefix).await?;
+        let mut metadata = vec![0; u32::from_be_bytes(prefix) as usize];
+        stdin.read_exact(&mut metadata).await?;
+        stdin.read_exact(&mut prefix).await?;
+
+        let frame = |data: &[u8]| Frame {
+            stream: StdStream::Stdout,
+            data: Bytes::copy_from_slice(data),
+        };
+        stdout.send(frame(&prefix)).await.unwrap();
+        let mut left = u32::from_be_bytes(prefix) as usize;
+        let mut chunk = vec![0; 4096];
+        while left > 0 {
+            let read = stdin.read(&mut chunk[..left.min(4096)]).await?;
+            stdout.send(frame(&chunk[..read])).await.unwrap();
+            left -= read;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_exchange_large_request() {
+        // Request far over what stdin buffers, function answers as it reads
+        let (mut stdin, function_stdin) = tokio::io::duplex(64 * 1024);
+        let (function_stdout, frames) = mpsc::channel(1);
+        let mut output: FrameStream = stream::unfold(frames, |mut frames| async move {
+            let frame = frames.recv().await?;
+            Some((Ok(frame), frames))
+        })
+        .boxed();
+        tokio::spawn(echo(function_stdin, function_stdout));
+
+        let body: Vec<u8> = (0..1024 * 1024).map(|i| i as u8).collect();
+        let mut message =
+            BytesMut::from(&request_metadata(&["REQUEST_METHOD=POST".to_string()])[..]);
+        message.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        message.extend_from_slice(&body);
+
+        let mut stdout = BytesMut::new();
+        let run = exchange(
+            &mut stdin,
+            &mut output,
+            &mut stdout,
+            &message,
+            16 * 1024 * 1024,
+        );
+        let (reply, stderr, written) = tokio::time::timeout(Duration::from_secs(10), run)
+            .await
+            .expect("Exchange is stuck")
+            .unwrap();
+        assert!(matches!(reply, Reply::Response(response) if response == body));
+        assert!(stderr.is_empty());
+        assert!(written);
+    }
+}
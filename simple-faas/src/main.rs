@@ -1,16 +1,35 @@
+mod cleanup;
 mod config;
+mod images;
+mod invoke;
+mod pool;
+mod proxy;
+mod request;
+mod response;
 
-use self::config::Config;
+use self::cleanup::{Cleanup, Reap};
+use self::config::{Config, FunctionData, FunctionMode};
+use self::images::FunctionImages;
+use self::invoke::{FunctionContainer, InvokeResult, Invoker};
+use self::pool::{Lease, WarmPools};
+use self::request::{collect_body, FunctionRequest};
+use self::response::{
+    function_response, invoke_error_response, method_not_allowed_response, stream_response,
+    unavailable_response,
+};
+use anyhow::bail;
 use env_logger::Env;
-use log::{debug, info};
-use simple_faas_docker::client::Client as DockerClient;
+use log::{debug, info, warn};
+use simple_faas_docker::client::{Client as DockerClient, TlsConfig};
 use simple_faas_docker::v1_37::Api as DockerApi;
-use simple_faas_docker::v1_37::ContainerCreateArgs;
+use std::convert::identity;
+use std::env;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use warp::http::Response;
-use warp::Filter;
+use warp::hyper::Body;
 use warp::reject;
-use bytes::Bytes;
+use warp::reply::Response;
+use warp::Filter;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
@@ -26,29 +45,47 @@ async fn main() -> anyhow::Result<()> {
         config
             .docker_config
             .auths
-            .iter()
-            .map(|(host, _auth)| host)
+            .keys()
             .fold(String::new(), |a, b| a + ", " + b)
     );
 
-    info!("Pulling function images");
-    for (function_name, function) in config.functions.iter() {
-        debug!(
-            "Pulling function {} from {}",
-            function_name,
-            function.image.clone()
-        );
+    let api = docker_api(&config)?;
+    check_runtimes(&config, &api).await?;
+
+    let images = FunctionImages::new(config.clone(), api.clone());
+    images.spawn_pulls(config.pull_concurrency);
+    images.spawn_refreshes();
 
-        pull_image(function.image.clone(), config.clone()).await?;
+    let cleanup = Cleanup::spawn(api.clone());
+    let max_age = config.reaper.max_age;
+    match cleanup::reap(&api, &cleanup, &config.instance_id, max_age, Reap::Startup).await {
+        Ok(reaped) => info!(
+            "Reaped {} container(s) left by previous {} instance",
+            reaped, config.instance_id
+        ),
+        Err(e) => warn!("Failed to reap containers left by previous instance: {}", e),
     }
-    info!("Successfuly pulled all images");
+    cleanup::spawn_reaper(
+        api.clone(),
+        cleanup.clone(),
+        config.instance_id.clone(),
+        config.reaper.clone(),
+    );
+
+    let invoker = Invoker::new(config.clone(), api.clone(), cleanup.clone());
+    let pools = WarmPools::spawn(config.clone(), invoker.clone(), images.clone());
 
     let config = warp::any().map(move || config.clone());
+    let images = warp::any().map(move || images.clone());
+    let invoker = warp::any().map(move || invoker.clone());
+    let pools = warp::any().map(move || pools.clone());
 
-    let function_call_filter = warp::path!("functions" / String)
-        .and(warp::body::content_length_limit(1024 * 1024))
-        .and(warp::body::bytes())
+    let function_call_filter = warp::path!("functions" / String / ..)
+        .and(request::function_request())
         .and(config)
+        .and(images)
+        .and(invoker)
+        .and(pools)
         .and_then(function_call_handler);
 
     info!("Listening on {:?}", listen_host);
@@ -58,18 +95,14 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn pull_image(tag: String, config: Arc<Config>) -> anyhow::Result<()> {
-    let api = docker_api(config);
-    api.images().pull(tag).await?;
-
-    Ok(())
-}
-
 async fn function_call_handler(
     name: String,
-    body: Bytes, 
+    mut request: FunctionRequest,
     config: Arc<Config>,
-) -> Result<impl warp::Reply, warp::Rejection> {
+    images: FunctionImages,
+    invoker: Invoker,
+    pools: WarmPools,
+) -> Result<Response, warp::Rejection> {
     let iter_name = name.clone();
     let function = config
         .functions
@@ -77,60 +110,177 @@ async fn function_call_handler(
         .find(move |(f_name, _f_data)| *f_name == &iter_name)
         .map(|(_name, data)| data);
 
-    debug!("API INPUT CHECK");
-    dbg!(&body);
-    let input = match body.is_empty() {
-        true => None,
-        false => Some(body),
-    };
-    dbg!(&input);
-
     let function = match function {
         Some(f) => f,
-        None => return Err(reject())
+        None => return Err(reject()),
     };
+    if !function.accepts(&request.method) {
+        return Ok(method_not_allowed_response(&name, function).map(Body::from));
+    }
+    if let Err(e) = request.limit_body(function.request_limit()) {
+        return Ok(invoke_error_response(&e.into()).map(Body::from));
+    }
 
-    let response = match call_docker_function(function.image.clone(), input, config).await {
-        Ok(output) => Response::builder()
-            .status(200)
-            .body(output)
-            .expect("Failed to construct a response"),
-        Err(e) => Response::builder()
-            .status(500)
-            .body(format!("Failed to call function: {}", e))
-            .expect("Failed to construct a response"),
+    let image = match images.image(&name).await {
+        Ok(image) => image,
+        Err(reason) => return Ok(unavailable_response(&name, &reason).map(Body::from)),
+    };
+
+    if function.mode == FunctionMode::Http {
+        let response = match proxy_function(&name, function, image, request, invoker, pools).await {
+            Ok(response) => response,
+            Err(e) => invoke_error_response(&e).map(Body::from),
+        };
+        return Ok(response);
+    }
+
+    let call = call_function(&name, function, image, request, invoker, pools);
+    let response = match call.await {
+        Ok(response) => response,
+        Err(e) => invoke_error_response(&e).map(Body::from),
     };
 
     Ok(response)
 }
 
-async fn call_docker_function(name: String, input: Option<Bytes>, config: Arc<Config>) -> anyhow::Result<String> {
-    let api = docker_api(config);
-    let container_create_opts = ContainerCreateArgs {
-        Image: name.clone(),
-        Cmd: None,
-        AttachStdin: true,
-        OpenStdin: true,
-        Tty: true,
+async fn proxy_function(
+    name: &str,
+    function: &FunctionData,
+    image: String,
+    request: FunctionRequest,
+    invoker: Invoker,
+    pools: WarmPools,
+) -> InvokeResult<Response> {
+    let pool = match &function.pool {
+        Some(pool) => pool,
+        None => {
+            let container = invoker
+                .start(name, function, image, false, Vec::new())
+                .await?;
+            let address = server_address(&container);
+            return invoker
+                .proxy(function, address, request, container, identity)
+                .await;
+        }
+    };
+
+    let container = match pools.take(name, &image) {
+        Some(container) => container,
+        None => {
+            debug!("No warm container of {}, starting one", name);
+            invoker
+                .start(name, function, image, pool.reuse, Vec::new())
+                .await?
+        }
+    };
+    let address = server_address(&container);
+    match pool.reuse {
+        true => {
+            let lease = pools.lease(name, pool.max_warm(), container);
+            invoker
+                .proxy(function, address, request, lease, Lease::discard)
+                .await
+        }
+        false => {
+            invoker
+                .proxy(function, address, request, container, identity)
+                .await
+        }
+    }
+}
+
+fn server_address(container: &FunctionContainer) -> SocketAddr {
+    container
+        .address()
+        .expect("Function container in http mode without server")
+}
+
+async fn call_function(
+    name: &str,
+    function: &FunctionData,
+    image: String,
+    request: FunctionRequest,
+    invoker: Invoker,
+    pools: WarmPools,
+) -> InvokeResult<Response> {
+    let pool = match &function.pool {
+        Some(pool) => pool,
+        None => {
+            let events = invoker.call(name, function, image, request).await?;
+            return Ok(stream_response(name, function, events).await);
+        }
     };
-    let container = api.containers().create(container_create_opts).await?;
-    container.start().await?;
 
-    if let Some(input) = input {
-        container.send_to_stdin(input).await?;
+    let container = match pools.take(name, &image) {
+        Some(container) => container,
+        None => {
+            debug!("No warm container of {}, starting one", name);
+            invoker
+                .start(name, function, image, pool.reuse, Vec::new())
+                .await?
+        }
+    };
+    if !pool.reuse {
+        let events = invoker.call_warm(name, function, container, request);
+        return Ok(stream_response(name, function, events).await);
     }
 
-    container.wait().await?;
-    let function_logs = container.logs().await?;
-    container.delete().await?;
+    // Reuse protocol sends request length ahead of it
+    let request_env = request.cgi_env(name, &function.request_headers);
+    let input = collect_body(request.body).await?;
+    let (output, container) = invoker
+        .run_reused(function, container, &request_env, input)
+        .await?;
+    if let Some(container) = container {
+        pools.put_back(name, pool.max_warm(), container);
+    }
 
-    dbg!(&function_logs);
+    Ok(function_response(name, function, output).map(Body::from))
+}
 
-    Ok(function_logs)
+/// Makes sure daemon knows every runtime functions ask for.
+async fn check_runtimes(config: &Config, api: &DockerApi) -> anyhow::Result<()> {
+    let runtimes: Vec<(&String, &String)> = config
+        .functions
+        .iter()
+        .filter_map(|(name, function)| function.runtime.as_ref().map(|r| (name, r)))
+        .collect();
+    if runtimes.is_empty() {
+        return Ok(());
+    }
+
+    let info = api.system().info().await?;
+    for (function_name, runtime) in runtimes {
+        if !info.Runtimes.contains_key(runtime) {
+            let mut registered: Vec<&String> = info.Runtimes.keys().collect();
+            registered.sort();
+            bail!(
+                "Function {} runtime {} is not registered with docker daemon, available: {:?}",
+                function_name,
+                runtime,
+                registered
+            );
+        }
+        debug!("Function {} runs with {} runtime", function_name, runtime);
+    }
+
+    Ok(())
 }
 
-fn docker_api(config: Arc<Config>) -> DockerApi {
-    let client = DockerClient::new(config.docker_host.clone());
-    let api = DockerApi::new(client, config.docker_config.clone());
-    api
+fn docker_api(config: &Config) -> anyhow::Result<DockerApi> {
+    let host = match &config.docker_host {
+        Some(host) => host.clone(),
+        None => env::var("DOCKER_HOST").unwrap_or_default(),
+    };
+    let tls = match &config.docker_tls {
+        Some(tls) => Some(tls.clone()),
+        None => TlsConfig::from_env()?,
+    };
+    let client = DockerClient::new(host, tls)?;
+
+    Ok(DockerApi::with_pool(
+        client,
+        config.docker_config.clone(),
+        config.docker_pool.clone(),
+    ))
 }
@@ -0,0 +1,190 @@
+use crate::config::Config;
+use crate::images::FunctionImages;
+use crate::invoke::{FunctionContainer, Invoker};
+use log::{debug, warn};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// How often pools are topped up and trimmed when no call wakes them up.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Idle {
+    container: FunctionContainer,
+    since: Instant,
+}
+
+struct FunctionPool {
+    /// Oldest idle container in front.
+    idle: Mutex<VecDeque<Idle>>,
+    /// Wakes up maintenance once a container is taken.
+    taken: Notify,
+}
+
+/// Reused container busy with a call, returned to the pool once dropped.
+///
+/// Lets a proxied response body keep its container until the body is sent.
+pub struct Lease {
+    container: Option<FunctionContainer>,
+    pools: WarmPools,
+    name: String,
+    max_warm: usize,
+}
+
+impl Lease {
+    /// Takes container out of the lease, so it is not returned to the pool.
+    pub fn discard(mut self) -> FunctionContainer {
+        self.container.take().expect("Lease without container")
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if let Some(container) = self.container.take() {
+            self.pools.put_back(&self.name, self.max_warm, container);
+        }
+    }
+}
+
+/// Started containers of functions with `pool` setting. Cheap to clone.
+///
+/// Containers are created ahead of calls, so request description is not in their
+/// environment, see [Invoker::call_warm] and [Invoker::run_reused].
+#[derive(Clone)]
+pub struct WarmPools {
+    pools: Arc<HashMap<String, FunctionPool>>,
+}
+
+impl WarmPools {
+    /// Creates pools and starts keeping them filled in background.
+    pub fn spawn(config: Arc<Config>, invoker: Invoker, images: FunctionImages) -> Self {
+        let pools = config
+            .functions
+            .iter()
+            .filter(|(_name, function)| function.pool.is_some())
+            .map(|(name, _function)| {
+                let pool = FunctionPool {
+                    idle: Mutex::new(VecDeque::new()),
+                    taken: Notify::new(),
+                };
+                (name.clone(), pool)
+            })
+            .collect();
+        let pools = WarmPools {
+            pools: Arc::new(pools),
+        };
+
+        for name in pools.pools.keys() {
+            tokio::spawn(pools.clone().maintain(
+                name.clone(),
+                config.clone(),
+                invoker.clone(),
+                images.clone(),
+            ));
+        }
+
+        pools
+    }
+
+    /// Idle container of function created from `image`.
+    /// Containers of image replaced by refresh are dropped on the way.
+    pub fn take(&self, name: &str, image: &str) -> Option<FunctionContainer> {
+        let pool = self.pools.get(name)?;
+        let container = {
+            let mut idle = pool.idle.lock().expect("Pool lock poisoned");
+            // Most recently used first, so the rest can expire
+            std::iter::from_fn(|| idle.pop_back())
+                .map(|idle| idle.container)
+                .find(|container| container.image() == image)
+        };
+        pool.taken.notify_one();
+
+        container
+    }
+
+    /// Returns reused container to the pool, unless it has `max_warm` idle containers already.
+    pub fn put_back(&self, name: &str, max_warm: usize, container: FunctionContainer) {
+        let pool = match self.pools.get(name) {
+            Some(pool) => pool,
+            None => return,
+        };
+
+        let mut idle = pool.idle.lock().expect("Pool lock poisoned");
+        if idle.len() < max_warm {
+            idle.push_back(Idle {
+                container,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    /// Hands `container` back on drop, see [Lease].
+    pub fn lease(&self, name: &str, max_warm: usize, container: FunctionContainer) -> Lease {
+        Lease {
+            container: Some(container),
+            pools: self.clone(),
+            name: name.to_string(),
+            max_warm,
+        }
+    }
+
+    async fn maintain(
+        self,
+        name: String,
+        config: Arc<Config>,
+        invoker: Invoker,
+        images: FunctionImages,
+    ) {
+        let function = &config.functions[&name];
+        let settings = function
+            .pool
+            .clone()
+            .expect("Pool of function without pool");
+        let pool = &self.pools[&name];
+
+        loop {
+            let image = images.image(&name).await;
+            {
+                let mut idle = pool.idle.lock().expect("Pool lock poisoned");
+                if let Ok(image) = &image {
+                    idle.retain(|idle| idle.container.image() == image);
+                }
+                while idle.len() > settings.min_warm
+                    && idle
+                        .front()
+                        .is_some_and(|i| i.since.elapsed() > settings.idle_ttl)
+                {
+                    idle.pop_front();
+                    debug!("Expired idle container of {}", name);
+                }
+            }
+
+            if let Ok(image) = image {
+                while pool.idle.lock().expect("Pool lock poisoned").len() < settings.min_warm {
+                    match invoker
+                        .start(&name, function, image.clone(), true, Vec::new())
+                        .await
+                    {
+                        Ok(container) => {
+                            debug!("Started warm container of {}", name);
+                            pool.idle
+                                .lock()
+                                .expect("Pool lock poisoned")
+                                .push_back(Idle {
+                                    container,
+                                    since: Instant::now(),
+                                });
+                        }
+                        Err(e) => {
+                            warn!("Failed to start warm container of {}: {}", name, e);
+                            break;
+                        }
+                    }
+                }
+            }
+
+            let _ = tokio::time::timeout(MAINTENANCE_INTERVAL, pool.taken.notified()).await;
+        }
+    }
+}
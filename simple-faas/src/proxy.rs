@@ -0,0 +1,265 @@
+use crate::config::HttpServer;
+use crate::request::FunctionRequest;
+use futures_util::future::{self, BoxFuture, FutureExt};
+use futures_util::stream::{self, StreamExt};
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderValue, CONNECTION, CONTENT_LENGTH, HOST};
+use hyper::{Body, Client, HeaderMap, Request, Response, Uri};
+use log::trace;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+/// Headers describing a single connection rather than the message, never forwarded.
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+const FORWARDED_HOST_HEADER: &str = "X-Forwarded-Host";
+/// Pause between readiness probes of starting function server.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub type HttpClient = Client<HttpConnector>;
+
+pub fn http_client() -> HttpClient {
+    // Docker hands out addresses of removed containers to new ones,
+    // so a kept alive connection could end up talking to another function.
+    Client::builder().pool_max_idle_per_host(0).build_http()
+}
+
+/// Probes function server until it is ready or [HttpServer::ready_timeout] passes.
+pub async fn wait_ready(
+    client: &HttpClient,
+    address: SocketAddr,
+    server: &HttpServer,
+) -> Result<(), String> {
+    let probe = async {
+        loop {
+            let ready = match &server.readiness_path {
+                Some(path) => match readiness_uri(address, path) {
+                    Ok(uri) => match client.get(uri).await {
+                        Ok(response) => response.status().is_success(),
+                        Err(e) => {
+                            trace!("Readiness probe of {} failed: {}", address, e);
+                            false
+                        }
+                    },
+                    Err(e) => return Err(format!("invalid readiness_path {:?}: {}", path, e)),
+                },
+                None => TcpStream::connect(address).await.is_ok(),
+            };
+            if ready {
+                return Ok(());
+            }
+
+            tokio::time::sleep(READY_POLL_INTERVAL).await;
+        }
+    };
+
+    match tokio::time::timeout(server.ready_timeout, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(format!(
+            "no ready response on {} in {:?}",
+            address, server.ready_timeout
+        )),
+    }
+}
+
+fn readiness_uri(address: SocketAddr, path: &str) -> Result<Uri, hyper::http::Error> {
+    let path = path.trim_start_matches('/');
+    Ok(Uri::try_from(format!("http://{}/{}", address, path))?)
+}
+
+/// Request to function server at `address` carrying incoming call.
+pub fn upstream_request(
+    address: SocketAddr,
+    request: FunctionRequest,
+) -> Result<Request<Body>, hyper::http::Error> {
+    let mut uri = format!("http://{}/{}", address, request.path);
+    if !request.query.is_empty() {
+        uri.push('?');
+        uri.push_str(&request.query);
+    }
+
+    let mut headers = request.headers;
+    strip_hop_by_hop(&mut headers);
+    if let Some(host) = headers.remove(HOST) {
+        headers.insert(FORWARDED_HOST_HEADER, host);
+    }
+    if let Some(remote) = request.remote {
+        let forwarded_for = match headers.get(FORWARDED_FOR_HEADER) {
+            Some(chain) => format!("{}, {}", chain.to_str().unwrap_or_default(), remote.ip()),
+            None => remote.ip().to_string(),
+        };
+        headers.insert(FORWARDED_FOR_HEADER, HeaderValue::try_from(forwarded_for)?);
+    }
+
+    let mut upstream = Request::builder()
+        .method(request.method)
+        .uri(uri)
+        .body(Body::wrap_stream(request.body))?;
+    *upstream.headers_mut() = headers;
+
+    Ok(upstream)
+}
+
+/// Function server response as sent back to the caller.
+pub fn downstream_response(mut response: Response<Body>) -> Response<Body> {
+    strip_hop_by_hop(response.headers_mut());
+    response
+}
+
+/// Fails response announcing body larger than `limit`,
+/// otherwise body is aborted once server sends too much.
+pub fn limit_response(response: Response<Body>, limit: u64) -> Option<Response<Body>> {
+    let announced = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    if announced.is_some_and(|length| length > limit) {
+        return None;
+    }
+
+    let mut received = 0;
+    Some(response.map(|body| {
+        Body::wrap_stream(body.map(move |chunk| {
+            let chunk = chunk?;
+            received += chunk.len() as u64;
+            match received > limit {
+                true => Err(io::Error::other(format!("response exceeds {} bytes", limit)).into()),
+                false => Ok::<_, Box<dyn std::error::Error + Send + Sync>>(chunk),
+            }
+        }))
+    }))
+}
+
+/// Holds `keep` until response body is sent or caller goes away.
+/// Body not sent by `deadline` is aborted and `keep` is handed to `on_timeout`.
+pub fn deadline_response<T, F>(
+    response: Response<Body>,
+    deadline: Option<Instant>,
+    keep: T,
+    on_timeout: F,
+) -> Response<Body>
+where
+    T: Send + 'static,
+    F: FnOnce(T) + Send + 'static,
+{
+    let expired: BoxFuture<'static, ()> = match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).boxed(),
+        None => future::pending().boxed(),
+    };
+
+    response.map(|body| {
+        let state = Some((body, expired, keep, on_timeout));
+        Body::wrap_stream(stream::unfold(state, |state| async move {
+            let (mut body, mut expired, keep, on_timeout) = state?;
+            tokio::select! {
+                chunk = body.next() => match chunk {
+                    Some(Ok(chunk)) => Some((Ok(chunk), Some((body, expired, keep, on_timeout)))),
+                    Some(Err(e)) => Some((Err(e.into()), None)),
+                    None => None,
+                },
+                _ = &mut expired => {
+                    on_timeout(keep);
+                    let error = io::Error::new(io::ErrorKind::TimedOut, "function timed out");
+                    Some((Err::<_, Box<dyn std::error::Error + Send + Sync>>(error.into()), None))
+                }
+            }
+        }))
+    })
+}
+
+/// Removes standard hop-by-hop headers and ones listed in `Connection`.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    for name in HOP_BY_HOP_HEADERS
+        .iter()
+        .copied()
+        .chain(listed.iter().map(String::as_str))
+    {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deadline_response, upstream_request};
+    use crate::request::FunctionRequest;
+    use bytes::Bytes;
+    use futures_util::stream::{self, StreamExt};
+    use hyper::http::{HeaderMap, HeaderValue, Method};
+    use hyper::{Body, Response};
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+    use tokio::time::Instant;
+
+    #[test]
+    fn test_upstream_request() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Host", HeaderValue::from_static("faas.example.com"));
+        headers.insert("Connection", HeaderValue::from_static("keep-alive, X-Hop"));
+        headers.insert("X-Hop", HeaderValue::from_static("1"));
+        headers.insert("X-Forwarded-For", HeaderValue::from_static("10.0.0.1"));
+        headers.insert("Accept", HeaderValue::from_static("text/plain"));
+        let request = FunctionRequest {
+            method: Method::POST,
+            path: "users/1".to_string(),
+            query: "full=true".to_string(),
+            headers,
+            remote: Some("192.168.1.5:40000".parse().unwrap()),
+            body: stream::iter([Ok(Bytes::from_static(b"{}"))]).boxed(),
+        };
+
+        let upstream = upstream_request("172.17.0.2:8080".parse().unwrap(), request).unwrap();
+        assert_eq!(upstream.method(), Method::POST);
+        assert_eq!(upstream.uri(), "http://172.17.0.2:8080/users/1?full=true");
+        let headers = upstream.headers();
+        assert_eq!(headers["Accept"], "text/plain");
+        assert_eq!(headers["X-Forwarded-Host"], "faas.example.com");
+        assert_eq!(headers["X-Forwarded-For"], "10.0.0.1, 192.168.1.5");
+        assert!(headers.get("Host").is_none());
+        assert!(headers.get("Connection").is_none());
+        assert!(headers.get("X-Hop").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deadline_response() {
+        let deadline = Some(Instant::now() + Duration::from_millis(50));
+
+        let (kept, released) = oneshot::channel::<()>();
+        let response = Response::new(Body::from("done"));
+        let response = deadline_response(response, deadline, kept, |_kept| panic!("timed out"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, Bytes::from_static(b"done"));
+        assert!(released.await.is_err());
+
+        let (timed_out, stopped) = oneshot::channel();
+        let chunks = stream::iter([Ok::<_, std::io::Error>(Bytes::from_static(b"partial"))])
+            .chain(stream::pending());
+        let response = Response::new(Body::wrap_stream(chunks));
+        let response = deadline_response(response, deadline, "container", move |kept| {
+            timed_out.send(kept).unwrap()
+        });
+        assert!(hyper::body::to_bytes(response.into_body()).await.is_err());
+        assert_eq!(stopped.await, Ok("container"));
+    }
+}
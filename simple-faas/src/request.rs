@@ -0,0 +1,225 @@
+use crate::config::HeaderFilter;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::stream::{self, BoxStream};
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use std::net::SocketAddr;
+use thiserror::Error;
+use warp::filters::path::Tail;
+use warp::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use warp::http::{HeaderMap, Method};
+use warp::{Filter, Rejection};
+
+#[derive(Debug, Error)]
+pub enum BodyError {
+    #[error("Request body is larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("Failed to read request body: {0}")]
+    Read(#[from] warp::Error),
+}
+
+/// Request body chunks as they arrive from caller.
+pub type RequestBody = BoxStream<'static, Result<Bytes, BodyError>>;
+
+/// Incoming function call.
+pub struct FunctionRequest {
+    pub method: Method,
+    /// Path after `/functions/<name>/`, empty for the function itself.
+    pub path: String,
+    /// Raw query string without `?`, empty when there is none.
+    pub query: String,
+    pub headers: HeaderMap,
+    pub remote: Option<SocketAddr>,
+    pub body: RequestBody,
+}
+
+impl FunctionRequest {
+    /// Fails request announcing body larger than `limit` right away,
+    /// otherwise its body fails once caller sends too much.
+    pub fn limit_body(&mut self, limit: u64) -> Result<(), BodyError> {
+        let announced = self
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<u64>().ok());
+        if announced.is_some_and(|length| length > limit) {
+            return Err(BodyError::TooLarge(limit));
+        }
+
+        let body = std::mem::replace(&mut self.body, stream::empty().boxed());
+        let mut received = 0;
+        self.body = body
+            .and_then(move |chunk| {
+                received += chunk.len() as u64;
+                let chunk = match received > limit {
+                    true => Err(BodyError::TooLarge(limit)),
+                    false => Ok(chunk),
+                };
+                async move { chunk }
+            })
+            .boxed();
+
+        Ok(())
+    }
+
+    /// CGI style environment describing request to function `name`,
+    /// headers passing `filter` become `HTTP_*` variables.
+    pub fn cgi_env(&self, name: &str, filter: &HeaderFilter) -> Vec<String> {
+        let mut env = vec![
+            format!("REQUEST_METHOD={}", self.method),
+            format!("SCRIPT_NAME=/functions/{}", name),
+            format!("PATH_INFO=/{}", self.path),
+            format!("QUERY_STRING={}", self.query),
+        ];
+        if let Some(remote) = self.remote {
+            env.push(format!("REMOTE_ADDR={}", remote.ip()));
+        }
+
+        for (header, value) in self.headers.iter() {
+            // Values that are not text can't be passed through environment
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            if header == CONTENT_TYPE {
+                env.push(format!("CONTENT_TYPE={}", value));
+            } else if header == CONTENT_LENGTH {
+                env.push(format!("CONTENT_LENGTH={}", value));
+            } else if filter.allows(header.as_str()) {
+                let variable = header.as_str().to_ascii_uppercase().replace('-', "_");
+                env.push(format!("HTTP_{}={}", variable, value));
+            }
+        }
+
+        env
+    }
+}
+
+/// Extracts everything about a call of `/functions/<name>/..` past the function name.
+pub fn function_request() -> impl Filter<Extract = (FunctionRequest,), Error = Rejection> + Clone {
+    let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+
+    warp::method()
+        .and(warp::path::tail())
+        .and(query)
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::body::stream().map(request_body))
+        .map(
+            |method, tail: Tail, query, headers, remote, body| FunctionRequest {
+                method,
+                path: tail.as_str().to_string(),
+                query,
+                headers,
+                remote,
+                body,
+            },
+        )
+}
+
+fn request_body<S, B>(body: S) -> RequestBody
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: Buf,
+{
+    body.map_ok(|mut chunk| chunk.copy_to_bytes(chunk.remaining()))
+        .map_err(BodyError::from)
+        .boxed()
+}
+
+/// Whole request body, for functions that need its length upfront.
+pub async fn collect_body(body: RequestBody) -> Result<Bytes, BodyError> {
+    let body = body
+        .try_fold(BytesMut::new(), |mut collected, chunk| async move {
+            collected.extend_from_slice(&chunk);
+            Ok(collected)
+        })
+        .await?;
+
+    Ok(body.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect_body, BodyError, FunctionRequest};
+    use crate::config::HeaderFilter;
+    use bytes::Bytes;
+    use futures_util::stream::{self, StreamExt};
+    use warp::http::{HeaderMap, HeaderValue, Method};
+
+    #[test]
+    fn test_cgi_env() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+        headers.insert("Accept-Language", HeaderValue::from_static("en"));
+        headers.insert("Authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("X-Debug", HeaderValue::from_static("1"));
+        let request = FunctionRequest {
+            method: Method::PUT,
+            path: "items/7".to_string(),
+            query: "dry=1".to_string(),
+            headers,
+            remote: Some("10.1.2.3:5000".parse().unwrap()),
+            body: stream::empty().boxed(),
+        };
+
+        let env = request.cgi_env("store", &HeaderFilter::default());
+        assert_eq!(
+            env,
+            vec![
+                "REQUEST_METHOD=PUT",
+                "SCRIPT_NAME=/functions/store",
+                "PATH_INFO=/items/7",
+                "QUERY_STRING=dry=1",
+                "REMOTE_ADDR=10.1.2.3",
+                "CONTENT_TYPE=application/json",
+                "HTTP_ACCEPT_LANGUAGE=en",
+                "HTTP_X_DEBUG=1",
+            ]
+        );
+
+        let filter = HeaderFilter {
+            allow: Some(vec!["Authorization".to_string(), "X-Debug".to_string()]),
+            deny: Some(vec!["x-debug".to_string()]),
+        };
+        let env = request.cgi_env("store", &filter);
+        assert!(env.contains(&"HTTP_AUTHORIZATION=Bearer secret".to_string()));
+        assert!(!env.iter().any(|v| v.starts_with("HTTP_X_DEBUG")));
+        assert!(!env.iter().any(|v| v.starts_with("HTTP_ACCEPT_LANGUAGE")));
+    }
+
+    fn chunked_request(
+        chunks: &'static [&'static [u8]],
+        length: Option<&'static str>,
+    ) -> FunctionRequest {
+        let mut headers = HeaderMap::new();
+        if let Some(length) = length {
+            headers.insert("Content-Length", HeaderValue::from_static(length));
+        }
+        let body = stream::iter(chunks.iter().map(|chunk| Ok(Bytes::from_static(chunk))));
+
+        FunctionRequest {
+            method: Method::POST,
+            path: String::new(),
+            query: String::new(),
+            headers,
+            remote: None,
+            body: body.boxed(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limit_body() {
+        let mut request = chunked_request(&[b"abc", b"def"], None);
+        request.limit_body(6).unwrap();
+        let body = collect_body(request.body).await.unwrap();
+        assert_eq!(body, Bytes::from_static(b"abcdef"));
+
+        let mut request = chunked_request(&[b"abc", b"def"], None);
+        request.limit_body(5).unwrap();
+        let result = collect_body(request.body).await;
+        assert!(matches!(result, Err(BodyError::TooLarge(5))));
+
+        let mut request = chunked_request(&[], Some("7"));
+        assert!(matches!(request.limit_body(6), Err(BodyError::TooLarge(6))));
+    }
+}
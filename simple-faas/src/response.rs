@@ -0,0 +1,491 @@
+use crate::config::{FunctionData, ResponseMode, StderrMode};
+use crate::invoke::{InvokeError, RunEvent, RunEvents};
+use crate::request::BodyError;
+use bytes::{Buf, Bytes, BytesMut};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use simple_faas_docker::error::DockerError;
+use simple_faas_docker::v1_37::ContainerLogs;
+use std::collections::HashMap;
+use std::time::Duration;
+use warp::http::header::{HeaderName, HeaderValue, ALLOW, CONTENT_TYPE, LOCATION};
+use warp::http::response::Builder;
+use warp::http::{HeaderMap, Response, StatusCode};
+use warp::hyper::{body, Body};
+
+/// Response header carrying base64 encoded function stderr for [StderrMode::Header].
+const STDERR_HEADER: &str = "X-Function-Stderr";
+/// Only this many trailing stderr bytes fit into response header.
+const STDERR_HEADER_LIMIT: usize = 4096;
+/// Response headers describing failed function container.
+const EXIT_CODE_HEADER: &str = "X-Function-Exit-Code";
+const OOM_KILLED_HEADER: &str = "X-Function-Oom-Killed";
+/// Headers gateway sets itself, function can't override them.
+const GATEWAY_HEADERS: [&str; 3] = ["connection", "content-length", "transfer-encoding"];
+
+/// Result of a single function invocation.
+#[derive(Debug, Default)]
+pub struct FunctionOutput {
+    pub logs: ContainerLogs,
+    pub exit_code: i64,
+    pub oom_killed: bool,
+    /// Daemon failure to wait for function container.
+    pub error: Option<String>,
+    /// Function was stopped after running for this long.
+    pub timed_out: Option<Duration>,
+    /// Function was killed for writing more than this many bytes.
+    pub output_limit: Option<u64>,
+}
+
+/// Status and headers function chose for its response.
+#[derive(Debug, Default, PartialEq)]
+struct FunctionHead {
+    status: Option<StatusCode>,
+    headers: HeaderMap,
+}
+
+impl FunctionHead {
+    fn add_header(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("invalid header name {:?}", name))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("invalid value of header {}", name))?;
+        if !GATEWAY_HEADERS.contains(&name.as_str()) {
+            self.headers.append(name, value);
+        }
+
+        Ok(())
+    }
+}
+
+/// Response of function in [ResponseMode::Json].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Envelope {
+    status_code: u16,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    is_base64_encoded: bool,
+}
+
+/// Response to function output received in whole.
+pub fn function_response(
+    name: &str,
+    function: &FunctionData,
+    output: FunctionOutput,
+) -> Response<Bytes> {
+    let mut response = Response::builder();
+    if !output.logs.stderr.is_empty() {
+        match function.stderr {
+            StderrMode::Log => log_stderr(name, &output.logs.stderr),
+            StderrMode::Header => {
+                let tail_start = output.logs.stderr.len().saturating_sub(STDERR_HEADER_LIMIT);
+                let stderr = base64::encode(&output.logs.stderr[tail_start..]);
+                response = response.header(STDERR_HEADER, stderr);
+            }
+        }
+    }
+
+    if let Some(body) = failure(name, &output) {
+        warn!("{}", body);
+        response = match (output.timed_out, output.output_limit) {
+            (Some(_), _) => response.status(504),
+            (None, Some(_)) => response.status(502),
+            (None, None) => response
+                .status(502)
+                .header(EXIT_CODE_HEADER, output.exit_code)
+                .header(OOM_KILLED_HEADER, output.oom_killed.to_string()),
+        };
+
+        return response
+            .body(body.into())
+            .expect("Failed to construct a response");
+    }
+
+    let stdout = output.logs.stdout;
+    let parsed = match function.response {
+        ResponseMode::Raw => Ok((FunctionHead::default(), stdout)),
+        ResponseMode::Cgi => match split_cgi_head(&stdout) {
+            Ok(Some((head, body_start))) => Ok((head, stdout.slice(body_start..))),
+            Ok(None) => Err("header block is not terminated by an empty line".to_string()),
+            Err(e) => Err(e),
+        },
+        ResponseMode::Json => parse_envelope(&stdout),
+    };
+    let (head, body) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return malformed_response(name, &e),
+    };
+
+    head_response(response, function, head)
+        .body(body)
+        .expect("Failed to construct a response")
+}
+
+/// Response to running function. With [FunctionData::stream] it is streamed as soon as
+/// function output decides its status, otherwise it is [function_response] once function exits.
+///
+/// Failure before response started is reported like in [function_response], after that
+/// response body is aborted so caller can tell it is incomplete.
+pub async fn stream_response(
+    name: &str,
+    function: &FunctionData,
+    mut events: RunEvents,
+) -> Response<Body> {
+    // Envelope and stderr header are only known once function exits
+    let buffered = !function.stream
+        || function.response == ResponseMode::Json
+        || function.stderr == StderrMode::Header;
+    let mut stdout = BytesMut::new();
+    let mut stderr = BytesMut::new();
+
+    loop {
+        match events.recv().await {
+            Some(Ok(RunEvent::Stdout(data))) => stdout.extend_from_slice(&data),
+            Some(Ok(RunEvent::Stderr(data))) => {
+                stderr.extend_from_slice(&data);
+                continue;
+            }
+            Some(Ok(RunEvent::Exited(mut output))) => {
+                output.logs = ContainerLogs {
+                    stdout: stdout.freeze(),
+                    stderr: stderr.freeze(),
+                };
+                return function_response(name, function, output).map(Body::from);
+            }
+            Some(Err(e)) => return invoke_error_response(&e).map(Body::from),
+            None => {
+                let e = format!("Function {} run ended without result", name);
+                return Response::builder()
+                    .status(502)
+                    .body(e.into())
+                    .expect("Failed to construct a response");
+            }
+        }
+        if buffered {
+            continue;
+        }
+
+        let head = match function.response {
+            ResponseMode::Cgi => match split_cgi_head(&stdout) {
+                Ok(Some((head, body_start))) => {
+                    stdout.advance(body_start);
+                    head
+                }
+                Ok(None) => continue,
+                Err(e) => return malformed_response(name, &e).map(Body::from),
+            },
+            ResponseMode::Raw | ResponseMode::Json => FunctionHead::default(),
+        };
+
+        let (sender, body) = Body::channel();
+        tokio::spawn(forward_output(
+            name.to_string(),
+            stdout.freeze(),
+            stderr,
+            events,
+            sender,
+        ));
+        return head_response(Response::builder(), function, head)
+            .body(body)
+            .expect("Failed to construct a response");
+    }
+}
+
+/// Sends the rest of function output as response body.
+async fn forward_output(
+    name: String,
+    received: Bytes,
+    mut stderr: BytesMut,
+    mut events: RunEvents,
+    mut sender: body::Sender,
+) {
+    let mut next = Some(received).filter(|data| !data.is_empty());
+    let failure = loop {
+        if let Some(data) = next.take() {
+            // Dropping events stops the function
+            if sender.send_data(data).await.is_err() {
+                debug!("Caller of function {} went away", name);
+                return;
+            }
+        }
+
+        match events.recv().await {
+            Some(Ok(RunEvent::Stdout(data))) => next = Some(data),
+            Some(Ok(RunEvent::Stderr(data))) => stderr.extend_from_slice(&data),
+            Some(Ok(RunEvent::Exited(output))) => break failure(&name, &output),
+            Some(Err(e)) => break Some(format!("Failed to call function {}: {}", name, e)),
+            None => break Some(format!("Function {} run ended without result", name)),
+        }
+    };
+
+    if !stderr.is_empty() {
+        log_stderr(&name, &stderr);
+    }
+    if let Some(failure) = failure {
+        warn!("{}, aborting its response", failure);
+        sender.abort();
+    }
+}
+
+/// Description of failed function run, `None` when it succeeded.
+fn failure(name: &str, output: &FunctionOutput) -> Option<String> {
+    if let Some(timeout) = output.timed_out {
+        return Some(format!("Function {} timed out after {:?}", name, timeout));
+    }
+    if let Some(limit) = output.output_limit {
+        return Some(format!(
+            "Function {} was cut off for output above {} bytes",
+            name, limit
+        ));
+    }
+    if output.exit_code != 0 || output.error.is_some() {
+        let mut body = format!("Function {} exited with code {}", name, output.exit_code);
+        if output.oom_killed {
+            body.push_str(", killed by out of memory killer");
+        }
+        if let Some(error) = &output.error {
+            body = format!("{}: {}", body, error);
+        }
+        return Some(body);
+    }
+
+    None
+}
+
+fn log_stderr(name: &str, stderr: &[u8]) {
+    info!(
+        "Function {} stderr: {}",
+        name,
+        String::from_utf8_lossy(stderr)
+    );
+}
+
+fn malformed_response(name: &str, error: &str) -> Response<Bytes> {
+    let body = format!("Function {} returned malformed response: {}", name, error);
+    warn!("{}", body);
+    Response::builder()
+        .status(502)
+        .body(body.into())
+        .expect("Failed to construct a response")
+}
+
+/// Status and headers of successful function response.
+fn head_response(mut response: Builder, function: &FunctionData, head: FunctionHead) -> Builder {
+    if !head.headers.contains_key(CONTENT_TYPE) {
+        if let Some(content_type) = &function.content_type {
+            response = response.header(CONTENT_TYPE, content_type);
+        }
+    }
+    for (name, value) in head.headers.iter() {
+        response = response.header(name, value);
+    }
+
+    response.status(head.status.unwrap_or(StatusCode::OK))
+}
+
+/// Parses `Name: value` lines up to the first empty line at the start of function output
+/// and returns where body starts, `None` until the empty line is received.
+/// `Status: 201 Created` line sets response status, `Location` alone redirects.
+fn split_cgi_head(stdout: &[u8]) -> Result<Option<(FunctionHead, usize)>, String> {
+    let mut head = FunctionHead::default();
+    let mut position = 0;
+
+    loop {
+        let line_end = match stdout[position..].iter().position(|b| *b == b'\n') {
+            Some(offset) => position + offset,
+            None => return Ok(None),
+        };
+        let line = &stdout[position..line_end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        position = line_end + 1;
+
+        if line.is_empty() {
+            if head.status.is_none() && head.headers.contains_key(LOCATION) {
+                head.status = Some(StatusCode::FOUND);
+            }
+            return Ok(Some((head, position)));
+        }
+
+        let line = std::str::from_utf8(line).map_err(|_| "header is not valid utf-8")?;
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("header line {:?} has no colon", line))?;
+        match name.trim().eq_ignore_ascii_case("status") {
+            true => {
+                let code = value.split_whitespace().next().unwrap_or_default();
+                head.status = Some(parse_status(code)?);
+            }
+            false => head.add_header(name, value)?,
+        }
+    }
+}
+
+/// Parses JSON `{"statusCode", "headers", "body", "isBase64Encoded"}` envelope.
+fn parse_envelope(stdout: &[u8]) -> Result<(FunctionHead, Bytes), String> {
+    let envelope: Envelope = serde_json::from_slice(stdout)
+        .map_err(|e| format!("output is not a JSON response envelope: {}", e))?;
+
+    let mut head = FunctionHead {
+        status: Some(parse_status(&envelope.status_code.to_string())?),
+        headers: HeaderMap::new(),
+    };
+    for (name, value) in envelope.headers.iter() {
+        head.add_header(name, value)?;
+    }
+
+    let body = match envelope.is_base64_encoded {
+        true => base64::decode(&envelope.body)
+            .map_err(|e| format!("body is not valid base64: {}", e))?
+            .into(),
+        false => envelope.body.into(),
+    };
+
+    Ok((head, body))
+}
+
+fn parse_status(code: &str) -> Result<StatusCode, String> {
+    code.parse::<u16>()
+        .ok()
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .filter(|status| (200..600).contains(&status.as_u16()))
+        .ok_or_else(|| format!("invalid status {:?}", code))
+}
+
+pub fn unavailable_response(name: &str, reason: &str) -> Response<Bytes> {
+    Response::builder()
+        .status(503)
+        .body(format!("Function {} is unavailable: {}", name, reason).into())
+        .expect("Failed to construct a response")
+}
+
+pub fn method_not_allowed_response(name: &str, function: &FunctionData) -> Response<Bytes> {
+    let allowed = function.methods.clone().unwrap_or_default().join(", ");
+    Response::builder()
+        .status(405)
+        .header(ALLOW, allowed.to_ascii_uppercase())
+        .body(format!("Function {} does not accept this method", name).into())
+        .expect("Failed to construct a response")
+}
+
+pub fn invoke_error_response(error: &InvokeError) -> Response<Bytes> {
+    let status = match error {
+        InvokeError::Docker(e) => return docker_error_response(e),
+        InvokeError::Timeout(_) => 504,
+        InvokeError::BadRequest(_) | InvokeError::Body(BodyError::Read(_)) => 400,
+        InvokeError::Body(BodyError::TooLarge(_)) => 413,
+        InvokeError::NotReady(_) | InvokeError::Upstream(_) | InvokeError::ResponseTooLarge(_) => {
+            502
+        }
+    };
+    warn!("{}", error);
+
+    Response::builder()
+        .status(status)
+        .body(format!("Failed to call function: {}", error).into())
+        .expect("Failed to construct a response")
+}
+
+fn docker_error_response(error: &DockerError) -> Response<Bytes> {
+    let mut response = Response::builder();
+    response = match error {
+        DockerError::DaemonUnavailable { .. } => response.status(503).header("Retry-After", "1"),
+        DockerError::Timeout { .. } => response.status(504),
+        _ => response.status(502),
+    };
+
+    response
+        .body(format!("Failed to call function: {}", error).into())
+        .expect("Failed to construct a response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_envelope, split_cgi_head, stream_response, FunctionHead, FunctionOutput};
+    use crate::config::FunctionData;
+    use crate::invoke::RunEvent;
+    use bytes::Bytes;
+    use tokio::sync::mpsc;
+    use warp::http::StatusCode;
+    use warp::hyper::body;
+
+    #[test]
+    fn test_split_cgi_head() {
+        let stdout = Bytes::from_static(
+            b"Status: 201 Created\r\nContent-Type: image/png\r\nContent-Length: 1\r\nX-Other: 1\r\n\r\n\x89PNG\n",
+        );
+        let (head, body_start) = split_cgi_head(&stdout).unwrap().unwrap();
+        assert_eq!(head.status, Some(StatusCode::CREATED));
+        assert_eq!(head.headers["Content-Type"], "image/png");
+        assert_eq!(head.headers["X-Other"], "1");
+        assert!(head.headers.get("Content-Length").is_none());
+        assert_eq!(&stdout[body_start..], b"\x89PNG\n");
+
+        let (head, body_start) = split_cgi_head(b"\nbody").unwrap().unwrap();
+        assert_eq!(head, FunctionHead::default());
+        assert_eq!(body_start, 1);
+
+        let (head, _body_start) = split_cgi_head(b"Location: /next\n\n").unwrap().unwrap();
+        assert_eq!(head.status, Some(StatusCode::FOUND));
+
+        assert_eq!(split_cgi_head(b"Content-Type: text/plain\n"), Ok(None));
+        assert_eq!(split_cgi_head(b"Content-Ty"), Ok(None));
+        assert!(split_cgi_head(b"no colon\n\nbody").is_err());
+        assert!(split_cgi_head(b"Status: ok\n\nbody").is_err());
+        assert!(split_cgi_head(b"Bad Name: 1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_envelope() {
+        let stdout =
+            br#"{"statusCode": 404, "headers": {"Content-Type": "text/plain"}, "body": "missing"}"#;
+        let (head, body) = parse_envelope(stdout).unwrap();
+        assert_eq!(head.status, Some(StatusCode::NOT_FOUND));
+        assert_eq!(head.headers["Content-Type"], "text/plain");
+        assert_eq!(body, Bytes::from_static(b"missing"));
+
+        let stdout = br#"{"statusCode": 200, "body": "aGk=", "isBase64Encoded": true}"#;
+        let (_head, body) = parse_envelope(stdout).unwrap();
+        assert_eq!(body, Bytes::from_static(b"hi"));
+
+        assert!(parse_envelope(b"plain text").is_err());
+        assert!(parse_envelope(br#"{"body": "no status"}"#).is_err());
+        assert!(parse_envelope(br#"{"statusCode": 99}"#).is_err());
+        assert!(
+            parse_envelope(br#"{"statusCode": 200, "body": "!", "isBase64Encoded": true}"#)
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_response_failure() {
+        let run = |stream| async move {
+            let function: FunctionData =
+                serde_yaml::from_str(&format!("image: fail\nstream: {}\n", stream)).unwrap();
+            let (events, receiver) = mpsc::channel(4);
+            events
+                .send(Ok(RunEvent::Stdout(Bytes::from_static(b"partial"))))
+                .await
+                .unwrap();
+            let output = FunctionOutput {
+                exit_code: 3,
+                ..FunctionOutput::default()
+            };
+            events.send(Ok(RunEvent::Exited(output))).await.unwrap();
+            stream_response("fail", &function, receiver).await
+        };
+
+        let response = run(false).await;
+        assert_eq!(response.status(), 502);
+        assert_eq!(response.headers()["X-Function-Exit-Code"], "3");
+        assert_eq!(response.headers()["X-Function-Oom-Killed"], "false");
+
+        // Output already sent when function fails, only the body can tell
+        let response = run(true).await;
+        assert_eq!(response.status(), 200);
+        assert!(body::to_bytes(response.into_body()).await.is_err());
+    }
+}